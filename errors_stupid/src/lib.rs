@@ -67,12 +67,166 @@ impl SubStringError {
     }
 }
 
+#[derive(Debug)]
+pub struct CompressionError {
+    pub source: String,
+}
+
+impl Error for CompressionError {}
+
+impl Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CompressionError")
+    }
+}
+
+impl CompressionError {
+    pub fn new<T: Into<String>>(source: T) -> Self {
+        CompressionError {
+            source: source.into(),
+        }
+    }
+}
+
+/// The request could not be understood because the client sent something malformed, which should
+/// be answered with `status` (400 unless a more specific one applies) instead of being treated as
+/// a fault of the server.
+#[derive(Debug)]
+pub struct BadRequestError {
+    pub source: String,
+    pub status: u16,
+}
+
+impl Error for BadRequestError {}
+
+impl Display for BadRequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BadRequestError")
+    }
+}
+
+impl BadRequestError {
+    pub fn new<T: Into<String>>(source: T) -> Self {
+        BadRequestError {
+            source: source.into(),
+            status: 400,
+        }
+    }
+
+    /// A mistake of the client which gets a more specific 4xx (or 501/505) than 400, such as a
+    /// 414 for a request target which is too long.
+    pub fn with_status<T: Into<String>>(source: T, status: u16) -> Self {
+        BadRequestError {
+            source: source.into(),
+            status,
+        }
+    }
+}
+
+/// JSON which could not be parsed, `position` is the byte offset into the text where parsing
+/// failed.
+#[derive(Debug)]
+pub struct JsonError {
+    pub source: String,
+    pub position: usize,
+}
+
+impl Error for JsonError {}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JsonError at byte {}", self.position)
+    }
+}
+
+impl JsonError {
+    pub fn new<T: Into<String>>(source: T, position: usize) -> Self {
+        JsonError {
+            source: source.into(),
+            position,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CookieError {
+    pub source: String,
+}
+
+impl Error for CookieError {}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CookieError")
+    }
+}
+
+impl CookieError {
+    pub fn new<T: Into<String>>(source: T) -> Self {
+        CookieError {
+            source: source.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HeaderError {
+    pub source: String,
+}
+
+impl Error for HeaderError {}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HeaderError")
+    }
+}
+
+impl HeaderError {
+    pub fn new<T: Into<String>>(source: T) -> Self {
+        HeaderError {
+            source: source.into(),
+        }
+    }
+}
+
+/// The client sent more requests (or opened more connections) than it is allowed to, it may try
+/// again after `retry_after` seconds.
+#[derive(Debug)]
+pub struct TooManyRequestsError {
+    pub source: String,
+    pub retry_after: u64,
+}
+
+impl Error for TooManyRequestsError {}
+
+impl Display for TooManyRequestsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TooManyRequestsError, retry after {}s", self.retry_after)
+    }
+}
+
+impl TooManyRequestsError {
+    pub fn new<T: Into<String>>(source: T, retry_after: u64) -> Self {
+        TooManyRequestsError {
+            source: source.into(),
+            retry_after,
+        }
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum StdStupidError {
     HttpServer(HttpServerError),
     IntValue(IntValueError),
     SubString(SubStringError),
+    Compression(CompressionError),
+    BadRequest(BadRequestError),
+    Json(JsonError),
+    Cookie(CookieError),
+    Header(HeaderError),
+    TooManyRequests(TooManyRequestsError),
     Utf8Parsing(std::str::Utf8Error),
     ParseFloat(std::num::ParseFloatError),
     StdIO(std::io::Error),
@@ -106,6 +260,42 @@ impl From<SubStringError> for StdStupidError {
     }
 }
 
+impl From<CompressionError> for StdStupidError {
+    fn from(error: CompressionError) -> Self {
+        Self::Compression(error)
+    }
+}
+
+impl From<BadRequestError> for StdStupidError {
+    fn from(error: BadRequestError) -> Self {
+        Self::BadRequest(error)
+    }
+}
+
+impl From<JsonError> for StdStupidError {
+    fn from(error: JsonError) -> Self {
+        Self::Json(error)
+    }
+}
+
+impl From<CookieError> for StdStupidError {
+    fn from(error: CookieError) -> Self {
+        Self::Cookie(error)
+    }
+}
+
+impl From<HeaderError> for StdStupidError {
+    fn from(error: HeaderError) -> Self {
+        Self::Header(error)
+    }
+}
+
+impl From<TooManyRequestsError> for StdStupidError {
+    fn from(error: TooManyRequestsError) -> Self {
+        Self::TooManyRequests(error)
+    }
+}
+
 impl From<Utf8Error> for StdStupidError {
     fn from(error: Utf8Error) -> Self {
         Self::Utf8Parsing(error)
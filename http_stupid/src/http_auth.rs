@@ -0,0 +1,882 @@
+use std::{
+    collections::HashMap,
+    fmt, str,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::prelude::*;
+use errors_stupid::StdStupidError;
+use standard_stupid::{
+    hashing::{hmac_sha256, md5, sha256, to_hex_string},
+    random_bytes,
+};
+use tracing::{debug, error};
+
+use crate::http_middleware::{HttpMiddleware, MiddlewareAction};
+use crate::http_query::normalize_path;
+use crate::http_struct::{HttpRequestType, HttpResponseStruct, ParseReturnData};
+
+/// Somewhere usernames and passwords can be checked against for HTTP Basic authentication.
+pub trait CredentialStore: fmt::Debug + Send + Sync {
+    fn verify(&self, username: &str, password: &str) -> bool;
+}
+
+/// A map of username to password works as a credential store.
+impl CredentialStore for HashMap<String, String> {
+    fn verify(&self, username: &str, password: &str) -> bool {
+        match self.get(username) {
+            Some(expected) => constant_time_eq(expected.as_bytes(), password.as_bytes()),
+            None => false,
+        }
+    }
+}
+
+/// Function which decides if a Bearer token is valid.
+pub type BearerValidator = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Hash algorithms which can be used for HTTP Digest authentication.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    /// The name of the algorithm as used in the `algorithm` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("MD5") {
+            Some(DigestAlgorithm::Md5)
+        } else if name.eq_ignore_ascii_case("SHA-256") {
+            Some(DigestAlgorithm::Sha256)
+        } else {
+            None
+        }
+    }
+
+    /// Hashes the text and returns it as lowercase hex, which is the H() function of RFC 7616.
+    pub fn hash<T: AsRef<str>>(&self, text: T) -> String {
+        match self {
+            DigestAlgorithm::Md5 => to_hex_string(&md5(text.as_ref().as_bytes())),
+            DigestAlgorithm::Sha256 => to_hex_string(&sha256(text.as_ref().as_bytes())),
+        }
+    }
+}
+
+/// Somewhere the HA1 of a user can be looked up for HTTP Digest authentication, which is
+/// `H(username:realm:password)`. As the password is never sent with Digest the server has to be
+/// able to calculate this itself.
+pub trait DigestCredentialStore: fmt::Debug + Send + Sync {
+    fn ha1(&self, username: &str, realm: &str, algorithm: DigestAlgorithm) -> Option<String>;
+}
+
+/// A map of username to plain text password works as a Digest credential store.
+impl DigestCredentialStore for HashMap<String, String> {
+    fn ha1(&self, username: &str, realm: &str, algorithm: DigestAlgorithm) -> Option<String> {
+        self.get(username)
+            .map(|password| algorithm.hash(format!("{username}:{realm}:{password}")))
+    }
+}
+
+/// How many bytes of a nonce are signed, the time it was issued and random bytes so nonces issued
+/// at the same time differ. The signature is the first half of their HMAC.
+const SIGNED_NONCE_LENGTH: usize = 16;
+
+#[derive(Debug)]
+struct UsedNonce {
+    issued: Instant,
+    highest_nonce_count: u32,
+}
+
+/// Everything Digest authentication needs to keep between requests. Nonces are signed with a
+/// secret instead of being stored, so only the nonces which were used with valid credentials
+/// take up memory, to remember the highest nonce count seen so a captured request can't be
+/// replayed.
+#[derive(Clone)]
+pub struct DigestState {
+    credential_store: Arc<dyn DigestCredentialStore>,
+    secret: Vec<u8>,
+    started: Instant,
+    used_nonces: Arc<Mutex<HashMap<String, UsedNonce>>>,
+    nonce_lifetime: Duration,
+    opaque: String,
+}
+
+impl fmt::Debug for DigestState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DigestState")
+            .field("credential_store", &self.credential_store)
+            .field("nonce_lifetime", &self.nonce_lifetime)
+            .field("opaque", &self.opaque)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DigestState {
+    /// Hands out a new nonce, which is the time it was issued and random bytes signed with the
+    /// secret so nothing has to be kept about it.
+    fn issue_nonce(&self) -> Result<String, StdStupidError> {
+        let issued = self.started.elapsed().as_millis() as u64;
+        let mut nonce = issued.to_be_bytes().to_vec();
+        nonce.extend(random_bytes(SIGNED_NONCE_LENGTH - nonce.len())?);
+
+        let signature = hmac_sha256(&self.secret, &nonce);
+        nonce.extend_from_slice(&signature[..SIGNED_NONCE_LENGTH]);
+
+        Ok(BASE64_STANDARD.encode(nonce))
+    }
+
+    /// Checks the nonce was signed with the secret, returning when it was issued.
+    fn nonce_issued(&self, nonce: &str) -> Option<Instant> {
+        let nonce = BASE64_STANDARD.decode(nonce).ok()?;
+        if nonce.len() != SIGNED_NONCE_LENGTH * 2 {
+            return None;
+        }
+
+        let (signed, signature) = nonce.split_at(SIGNED_NONCE_LENGTH);
+        let expected = hmac_sha256(&self.secret, signed);
+        if !constant_time_eq(&expected[..SIGNED_NONCE_LENGTH], signature) {
+            return None;
+        }
+
+        let issued = u64::from_be_bytes(signed[..8].try_into().ok()?);
+        self.started.checked_add(Duration::from_millis(issued))
+    }
+}
+
+#[derive(Clone)]
+pub enum AuthScheme {
+    Basic(Arc<dyn CredentialStore>),
+    Bearer(BearerValidator),
+    Digest(DigestState),
+}
+
+impl fmt::Debug for AuthScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthScheme::Basic(store) => f.debug_tuple("Basic").field(store).finish(),
+            AuthScheme::Bearer(_) => f.debug_tuple("Bearer").finish_non_exhaustive(),
+            AuthScheme::Digest(state) => f.debug_tuple("Digest").field(state).finish(),
+        }
+    }
+}
+
+/// Why a Digest authorization was not accepted, a stale nonce means the credentials were right
+/// so the client can retry with a new nonce without asking the user again.
+#[derive(Debug, PartialEq)]
+enum DigestFailure {
+    Invalid,
+    Stale,
+}
+
+/// Middleware which requires requests to paths under one of the path prefixes to carry a valid
+/// `Authorization` header, and answers with a 401 and a `WWW-Authenticate` challenge otherwise.
+/// With no path prefixes every path is protected.
+///
+/// OPTIONS requests are let through as browsers never send credentials on a CORS preflight.
+#[derive(Debug, Clone)]
+pub struct AuthMiddleware {
+    realm: String,
+    path_prefixes: Vec<String>,
+    scheme: AuthScheme,
+}
+
+impl AuthMiddleware {
+    /// Creates a middleware checking HTTP Basic credentials (RFC 7617) against the store given.
+    pub fn basic<C: CredentialStore + 'static>(
+        realm: &str,
+        path_prefixes: &[&str],
+        credential_store: C,
+    ) -> Self {
+        AuthMiddleware {
+            realm: realm.to_string(),
+            path_prefixes: path_prefixes.iter().map(|p| p.to_string()).collect(),
+            scheme: AuthScheme::Basic(Arc::new(credential_store)),
+        }
+    }
+
+    /// Creates a middleware checking Bearer tokens (RFC 6750) with the validator given.
+    pub fn bearer<F>(realm: &str, path_prefixes: &[&str], validator: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        AuthMiddleware {
+            realm: realm.to_string(),
+            path_prefixes: path_prefixes.iter().map(|p| p.to_string()).collect(),
+            scheme: AuthScheme::Bearer(Arc::new(validator)),
+        }
+    }
+
+    /// Creates a middleware using HTTP Digest authentication (RFC 7616) with `qop=auth`, offering
+    /// both SHA-256 and MD5. Nonces are valid for `nonce_lifetime`, and every request has to use a
+    /// higher nonce count than the last one for its nonce. Nonces are signed with a secret which
+    /// is picked when the middleware is created, so they stop being valid on a restart, and
+    /// creating it fails if no random bytes could be read for that secret.
+    pub fn digest<C: DigestCredentialStore + 'static>(
+        realm: &str,
+        path_prefixes: &[&str],
+        credential_store: C,
+        nonce_lifetime: Duration,
+    ) -> Result<Self, StdStupidError> {
+        Ok(AuthMiddleware {
+            realm: realm.to_string(),
+            path_prefixes: path_prefixes.iter().map(|p| p.to_string()).collect(),
+            scheme: AuthScheme::Digest(DigestState {
+                credential_store: Arc::new(credential_store),
+                secret: random_bytes(32)?,
+                started: Instant::now(),
+                used_nonces: Arc::new(Mutex::new(HashMap::new())),
+                nonce_lifetime,
+                opaque: BASE64_STANDARD.encode(random_bytes(24)?),
+            }),
+        })
+    }
+
+    /// Checks if the path falls under one of the path prefixes, `/admin` protects `/admin` and
+    /// `/admin/users` but not `/administrator`. The path is normalized first so `//admin` and
+    /// `/./admin` are protected as well.
+    pub fn protects_path(&self, request_path: &str) -> bool {
+        let request_path = normalize_path(request_path);
+
+        self.path_prefixes.is_empty()
+            || self
+                .path_prefixes
+                .iter()
+                .any(|prefix| path_has_prefix(&request_path, prefix))
+    }
+
+    fn unauthorized(&self, error: Option<&str>, stale: bool) -> HttpResponseStruct {
+        let mut response = HttpResponseStruct::new();
+
+        response.set_status(401);
+        response.add_default_headers();
+
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        let challenges = match (&self.scheme, error) {
+            (AuthScheme::Basic(_), _) => {
+                vec![format!("Basic realm=\"{realm}\", charset=\"UTF-8\"")]
+            }
+            (AuthScheme::Bearer(_), Some(error)) => {
+                vec![format!("Bearer realm=\"{realm}\", error=\"{error}\"")]
+            }
+            (AuthScheme::Bearer(_), None) => vec![format!("Bearer realm=\"{realm}\"")],
+            (AuthScheme::Digest(state), _) => {
+                let nonce = match state.issue_nonce() {
+                    Ok(nonce) => nonce,
+                    Err(e) => {
+                        error!("Failed to issue a Digest nonce: {:?}", e);
+                        response.set_status(500);
+                        return response;
+                    }
+                };
+                let stale = if stale { ", stale=true" } else { "" };
+
+                // Challenges are listed in order of preference
+                [DigestAlgorithm::Sha256, DigestAlgorithm::Md5]
+                    .iter()
+                    .map(|algorithm| {
+                        format!(
+                            "Digest realm=\"{realm}\", qop=\"auth\", algorithm={}, nonce=\"{nonce}\", opaque=\"{}\"{stale}",
+                            algorithm.as_str(),
+                            state.opaque
+                        )
+                    })
+                    .collect()
+            }
+        };
+
+        // The realm comes from the settings, if it can't go in a header the client just doesn't
+        // get told how to authenticate
+        for challenge in challenges {
+            if let Err(e) = response.append_header("WWW-Authenticate", challenge) {
+                error!(
+                    "Invalid WWW-Authenticate challenge for realm {:?}: {e:?}",
+                    self.realm
+                );
+            }
+        }
+
+        response
+    }
+
+    /// Checks a Digest authorization against the request, first checking the credentials are
+    /// right and only then if the nonce is still valid and the nonce count went up. The nonce
+    /// count is only recorded when `record_nonce_count` is set, so a request can be checked
+    /// before its body is read without the real check afterwards looking like a replay.
+    fn verify_digest(
+        &self,
+        state: &DigestState,
+        authorization: &str,
+        parse_return_data: &ParseReturnData,
+        record_nonce_count: bool,
+    ) -> Result<(), DigestFailure> {
+        let (scheme, parameters) = authorization
+            .trim()
+            .split_once(' ')
+            .ok_or(DigestFailure::Invalid)?;
+        if !scheme.eq_ignore_ascii_case("Digest") {
+            return Err(DigestFailure::Invalid);
+        }
+
+        let parameters = parse_auth_params(parameters);
+        let parameter = |name: &str| parameters.get(name).ok_or(DigestFailure::Invalid);
+
+        let algorithm = match parameters.get("algorithm") {
+            Some(name) => DigestAlgorithm::from_name(name).ok_or(DigestFailure::Invalid)?,
+            None => DigestAlgorithm::Md5,
+        };
+        let username = parameter("username")?;
+        let nonce = parameter("nonce")?;
+        let uri = parameter("uri")?;
+        let nc = parameter("nc")?;
+        let cnonce = parameter("cnonce")?;
+
+        if *parameter("realm")? != self.realm
+            || parameter("qop")? != "auth"
+            || *parameter("opaque")? != state.opaque
+            || *uri != parse_return_data.request_target
+        {
+            return Err(DigestFailure::Invalid);
+        }
+
+        let nonce_count = u32::from_str_radix(nc, 16).map_err(|_| DigestFailure::Invalid)?;
+
+        let ha1 = state
+            .credential_store
+            .ha1(username, &self.realm, algorithm)
+            .ok_or(DigestFailure::Invalid)?;
+        let expected = digest_response(
+            algorithm,
+            &ha1,
+            nonce,
+            nc,
+            cnonce,
+            parse_return_data.http_request_type.as_str(),
+            uri,
+        );
+
+        if !constant_time_eq(expected.as_bytes(), parameter("response")?.as_bytes()) {
+            return Err(DigestFailure::Invalid);
+        }
+
+        // A nonce which isn't ours (such as one from before a restart) is treated like an expired
+        // one, as the credentials were right
+        let issued = state.nonce_issued(nonce).ok_or(DigestFailure::Stale)?;
+        if issued.elapsed() > state.nonce_lifetime {
+            return Err(DigestFailure::Stale);
+        }
+
+        let mut used_nonces = state.used_nonces.lock().unwrap();
+        let highest_nonce_count = used_nonces
+            .get(nonce.as_str())
+            .map_or(0, |n| n.highest_nonce_count);
+        if nonce_count <= highest_nonce_count {
+            debug!("Digest nonce count did not go up, possible replay");
+            return Err(DigestFailure::Invalid);
+        }
+
+        if record_nonce_count {
+            if !used_nonces.contains_key(nonce.as_str()) {
+                used_nonces.retain(|_, n| n.issued.elapsed() <= state.nonce_lifetime);
+            }
+            used_nonces
+                .entry(nonce.to_string())
+                .or_insert(UsedNonce {
+                    issued,
+                    highest_nonce_count,
+                })
+                .highest_nonce_count = nonce_count;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the request has valid credentials if its path is protected, failing with the
+    /// `error` and `stale` the challenge should be sent with.
+    fn authorize(
+        &self,
+        parse_return_data: &ParseReturnData,
+        record_nonce_count: bool,
+    ) -> Result<(), (Option<&'static str>, bool)> {
+        if parse_return_data.http_request_type == HttpRequestType::OPTIONS
+            || !self.protects_path(&parse_return_data.request_path)
+        {
+            return Ok(());
+        }
+
+        let authorization = match parse_return_data.headers.get("Authorization") {
+            Some(a) => a,
+            None => return Err((None, false)),
+        };
+
+        match &self.scheme {
+            AuthScheme::Basic(credential_store) => match parse_basic_credentials(authorization) {
+                Some((username, password)) if credential_store.verify(&username, &password) => {
+                    Ok(())
+                }
+                _ => {
+                    debug!("Basic authentication failed");
+                    Err((None, false))
+                }
+            },
+            AuthScheme::Bearer(validator) => match parse_bearer_token(authorization) {
+                Some(token) if validator(token) => Ok(()),
+                Some(_) => {
+                    debug!("Bearer token was rejected");
+                    Err((Some("invalid_token"), false))
+                }
+                None => Err((Some("invalid_request"), false)),
+            },
+            AuthScheme::Digest(state) => self
+                .verify_digest(state, authorization, parse_return_data, record_nonce_count)
+                .map_err(|failure| {
+                    debug!("Digest authentication failed: {:?}", failure);
+                    (None, failure == DigestFailure::Stale)
+                }),
+        }
+    }
+}
+
+impl HttpMiddleware for AuthMiddleware {
+    fn before_request(&self, parse_return_data: &mut ParseReturnData) -> MiddlewareAction {
+        match self.authorize(parse_return_data, true) {
+            Ok(()) => MiddlewareAction::Continue,
+            Err((error, stale)) => MiddlewareAction::Respond(self.unauthorized(error, stale)),
+        }
+    }
+
+    /// Bodies sent with `Expect: 100-continue` to a protected path are only accepted with valid
+    /// credentials, so they are refused before being read instead of being read for a 401.
+    fn accepts_body(&self, parse_return_data: &ParseReturnData) -> bool {
+        self.authorize(parse_return_data, false).is_ok()
+    }
+}
+
+/// Checks if the path is the prefix or is below it, so a prefix only matches whole segments.
+pub fn path_has_prefix(request_path: &str, prefix: &str) -> bool {
+    match request_path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Takes the value of an `Authorization` header using the Basic scheme and returns the username
+/// and password inside of it, or None if it is not valid Basic credentials.
+pub fn parse_basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = authorization.trim().split_once(' ')?;
+
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+
+    let decoded = BASE64_STANDARD.decode(credentials.trim()).ok()?;
+    let (username, password) = str::from_utf8(&decoded).ok()?.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Takes the value of an `Authorization` header using the Bearer scheme and returns the token,
+/// or None if it is not a valid token68.
+pub fn parse_bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+
+    let is_token68 = !token.is_empty()
+        && token.trim_end_matches('=').bytes().all(|b| {
+            b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'+' | b'/')
+        });
+
+    if scheme.eq_ignore_ascii_case("Bearer") && is_token68 {
+        Some(token)
+    } else {
+        None
+    }
+}
+
+/// Calculates the `response` parameter of a Digest authorization using `qop=auth`, which is
+/// `H(HA1:nonce:nc:cnonce:auth:H(method:uri))`.
+pub fn digest_response(
+    algorithm: DigestAlgorithm,
+    ha1: &str,
+    nonce: &str,
+    nc: &str,
+    cnonce: &str,
+    method: &str,
+    uri: &str,
+) -> String {
+    let ha2 = algorithm.hash(format!("{method}:{uri}"));
+
+    algorithm.hash(format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"))
+}
+
+/// Parses a list of auth-params such as `realm="x", nc=00000001` into a map from the lowercased
+/// parameter name to its value, with quoted values unescaped.
+pub fn parse_auth_params(parameters: &str) -> HashMap<String, String> {
+    let mut parsed: HashMap<String, String> = HashMap::new();
+    let mut characters = parameters.chars().peekable();
+
+    loop {
+        while characters
+            .peek()
+            .is_some_and(|c| c.is_whitespace() || *c == ',')
+        {
+            characters.next();
+        }
+
+        let name: String = characters
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            break;
+        }
+
+        while characters.peek().is_some_and(|c| c.is_whitespace()) {
+            characters.next();
+        }
+
+        let mut value = String::new();
+        if characters.peek() == Some(&'"') {
+            characters.next();
+            while let Some(c) = characters.next() {
+                match c {
+                    '\\' => value.extend(characters.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = characters.peek() {
+                if *c == ',' {
+                    break;
+                }
+                value.push(*c);
+                characters.next();
+            }
+            value = value.trim().to_string();
+        }
+
+        parsed.insert(name, value);
+    }
+
+    parsed
+}
+
+/// Compares the two without returning early, so the time taken doesn't tell an attacker how much
+/// of a password was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let mut difference = a.len() ^ b.len();
+
+    for i in 0..a.len().max(b.len()) {
+        difference |= (a.get(i).unwrap_or(&0) ^ b.get(i).unwrap_or(&0)) as usize;
+    }
+
+    difference == 0
+}
+
+#[cfg(test)]
+mod http_auth_tests {
+    use crate::http_auth::*;
+    use crate::http_headers::HeaderMap;
+    use crate::http_struct::HttpServerSettings;
+    use errors_stupid::StdStupidError;
+
+    fn request(request_path: &str, authorization: Option<&str>) -> ParseReturnData {
+        let mut headers = HeaderMap::new();
+        if let Some(authorization) = authorization {
+            headers.insert("Authorization".to_string(), authorization.to_string());
+        }
+
+        ParseReturnData {
+            request_target: request_path.to_string(),
+            request_path: request_path.to_string(),
+            headers,
+            ..Default::default()
+        }
+    }
+
+    fn status_and_challenge(action: MiddlewareAction) -> Option<(Vec<u8>, String)> {
+        match action {
+            MiddlewareAction::Continue => None,
+            MiddlewareAction::Respond(mut response) => {
+                let challenge = response.get_header("WWW-Authenticate").unwrap().to_string();
+                Some((response.get_response()[9..12].to_vec(), challenge))
+            }
+        }
+    }
+
+    fn basic_middleware() -> AuthMiddleware {
+        let credentials = HashMap::from([("admin".to_string(), "hunter2".to_string())]);
+
+        AuthMiddleware::basic("Admin area", &["/admin"], credentials)
+    }
+
+    #[test]
+    fn basic_accepts_valid_credentials() {
+        // admin:hunter2
+        let mut valid = request("/admin/users", Some("Basic YWRtaW46aHVudGVyMg=="));
+
+        assert_eq!(
+            basic_middleware().before_request(&mut valid),
+            MiddlewareAction::Continue
+        );
+    }
+
+    #[test]
+    fn basic_challenges_missing_and_wrong_credentials() {
+        // admin:wrong
+        for authorization in [None, Some("Basic YWRtaW46d3Jvbmc="), Some("Basic !!!")] {
+            let mut invalid = request("/admin", authorization);
+
+            let (status, challenge) =
+                status_and_challenge(basic_middleware().before_request(&mut invalid)).unwrap();
+
+            assert_eq!(status, b"401");
+            assert_eq!(challenge, "Basic realm=\"Admin area\", charset=\"UTF-8\"");
+        }
+    }
+
+    #[test]
+    fn unprotected_paths_pass() {
+        let mut public = request("/administrator", None);
+
+        assert_eq!(
+            basic_middleware().before_request(&mut public),
+            MiddlewareAction::Continue
+        );
+    }
+
+    #[test]
+    fn unnormalized_paths_are_still_protected() {
+        for path in ["//admin/secret.html", "/./admin", "/public/../admin/"] {
+            assert!(basic_middleware().protects_path(path), "{path}");
+        }
+
+        let mut parsed = crate::http_parser::parse_http_connection(
+            b"GET //admin/secret.html HTTP/1.1\r\nHost: a\r\n\r\n",
+            &Default::default(),
+        )
+        .unwrap();
+        assert_eq!(parsed.request_path, "/admin/secret.html");
+        let (status, _) =
+            status_and_challenge(basic_middleware().before_request(&mut parsed)).unwrap();
+        assert_eq!(status, b"401");
+    }
+
+    #[test]
+    fn bearer_uses_validator() {
+        let middleware = AuthMiddleware::bearer("api", &[], |token| token == "s3cr3t-token");
+
+        let mut valid = request("/anything", Some("Bearer s3cr3t-token"));
+        assert_eq!(
+            middleware.before_request(&mut valid),
+            MiddlewareAction::Continue
+        );
+
+        let mut invalid = request("/anything", Some("Bearer nope"));
+        let (status, challenge) =
+            status_and_challenge(middleware.before_request(&mut invalid)).unwrap();
+        assert_eq!(status, b"401");
+        assert_eq!(challenge, "Bearer realm=\"api\", error=\"invalid_token\"");
+    }
+
+    fn digest_middleware(nonce_lifetime: Duration) -> AuthMiddleware {
+        let credentials = HashMap::from([("Mufasa".to_string(), "Circle of Life".to_string())]);
+
+        AuthMiddleware::digest(
+            "http-auth@example.org",
+            &["/dir"],
+            credentials,
+            nonce_lifetime,
+        )
+        .unwrap()
+    }
+
+    /// Asks for a challenge and answers it the way a client would
+    fn digest_authorization(middleware: &AuthMiddleware, nc: &str) -> String {
+        let (_, challenge) =
+            status_and_challenge(middleware.before_request(&mut request("/dir/index.html", None)))
+                .unwrap();
+        let challenge = parse_auth_params(challenge.strip_prefix("Digest ").unwrap());
+
+        answer_digest(&challenge["nonce"], &challenge["opaque"], nc)
+    }
+
+    /// Answers a Digest challenge with the nonce given using the right credentials
+    fn answer_digest(nonce: &str, opaque: &str, nc: &str) -> String {
+        let ha1 = DigestAlgorithm::Sha256.hash("Mufasa:http-auth@example.org:Circle of Life");
+        let response = digest_response(
+            DigestAlgorithm::Sha256,
+            &ha1,
+            nonce,
+            nc,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+            "GET",
+            "/dir/index.html",
+        );
+
+        format!(
+            "Digest username=\"Mufasa\", realm=\"http-auth@example.org\", uri=\"/dir/index.html\", algorithm=SHA-256, nonce=\"{nonce}\", nc={nc}, cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, response=\"{response}\", opaque=\"{opaque}\""
+        )
+    }
+
+    #[test]
+    fn digest_matches_rfc_7616_examples() {
+        let nonce = "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v";
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+
+        for (algorithm, expected) in [
+            (DigestAlgorithm::Md5, "8ca523f5e9506fed4657c9700eebdbec"),
+            (
+                DigestAlgorithm::Sha256,
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+        ] {
+            let ha1 = algorithm.hash("Mufasa:http-auth@example.org:Circle of Life");
+
+            assert_eq!(
+                digest_response(
+                    algorithm,
+                    &ha1,
+                    nonce,
+                    "00000001",
+                    cnonce,
+                    "GET",
+                    "/dir/index.html"
+                ),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn digest_accepts_once_per_nonce_count() {
+        let middleware = digest_middleware(Duration::from_secs(60));
+        let authorization = digest_authorization(&middleware, "00000001");
+
+        let mut first = request("/dir/index.html", Some(&authorization));
+        assert_eq!(
+            middleware.before_request(&mut first),
+            MiddlewareAction::Continue
+        );
+
+        // Sending the exact same authorization again is a replay
+        let mut replay = request("/dir/index.html", Some(&authorization));
+        assert!(status_and_challenge(middleware.before_request(&mut replay)).is_some());
+    }
+
+    #[test]
+    fn refuses_body_without_valid_credentials() {
+        let middleware = basic_middleware();
+        assert!(!middleware.accepts_body(&request("/admin/upload", None)));
+        assert!(!middleware.accepts_body(&request("/admin/upload", Some("Basic d3Jvbmc6d3Jvbmc="))));
+        assert!(middleware.accepts_body(&request(
+            "/admin/upload",
+            Some("Basic YWRtaW46aHVudGVyMg==")
+        )));
+        assert!(middleware.accepts_body(&request("/public", None)));
+
+        // Checking the body must not use up the nonce count the request itself is sent with
+        let middleware = digest_middleware(Duration::from_secs(60));
+        let authorization = digest_authorization(&middleware, "00000001");
+        let mut upload = request("/dir/index.html", Some(&authorization));
+        assert!(middleware.accepts_body(&upload));
+        assert_eq!(
+            middleware.before_request(&mut upload),
+            MiddlewareAction::Continue
+        );
+
+        let settings = HttpServerSettings {
+            middleware: vec![std::sync::Arc::new(basic_middleware())],
+            ..Default::default()
+        };
+        let parsed = crate::http_parser::parse_http_connection(
+            b"POST /admin/upload HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n",
+            &Default::default(),
+        )
+        .unwrap();
+        let error = crate::http_parser::check_expectation(&settings, &parsed).unwrap_err();
+        assert!(matches!(error, StdStupidError::BadRequest(e) if e.status == 417));
+    }
+
+    #[test]
+    fn digest_only_keeps_used_nonces() {
+        let middleware = digest_middleware(Duration::from_secs(60));
+        let AuthScheme::Digest(state) = &middleware.scheme else {
+            unreachable!()
+        };
+
+        // Anonymous requests get a challenge each, but nothing is kept about them
+        for _ in 0..100 {
+            digest_authorization(&middleware, "00000001");
+        }
+        assert!(state.used_nonces.lock().unwrap().is_empty());
+
+        let authorization = digest_authorization(&middleware, "00000001");
+        let mut valid = request("/dir/index.html", Some(&authorization));
+        assert_eq!(
+            middleware.before_request(&mut valid),
+            MiddlewareAction::Continue
+        );
+        assert_eq!(state.used_nonces.lock().unwrap().len(), 1);
+
+        // A nonce which wasn't signed by us is refused, even with the right credentials
+        let forged = BASE64_STANDARD.encode([0; SIGNED_NONCE_LENGTH * 2]);
+        let mut forged = request(
+            "/dir/index.html",
+            Some(&answer_digest(&forged, &state.opaque, "00000001")),
+        );
+        let (status, challenge) =
+            status_and_challenge(middleware.before_request(&mut forged)).unwrap();
+        assert_eq!(status, b"401");
+        assert!(challenge.ends_with("stale=true"));
+        assert_eq!(state.used_nonces.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn digest_marks_expired_nonce_stale() {
+        let middleware = digest_middleware(Duration::ZERO);
+        let authorization = digest_authorization(&middleware, "00000001");
+
+        std::thread::sleep(Duration::from_millis(5));
+
+        let mut expired = request("/dir/index.html", Some(&authorization));
+        let (status, challenge) =
+            status_and_challenge(middleware.before_request(&mut expired)).unwrap();
+
+        assert_eq!(status, b"401");
+        assert!(challenge.ends_with("stale=true"));
+    }
+
+    #[test]
+    fn parses_credentials() {
+        assert_eq!(
+            parse_basic_credentials("basic dXNlcjpwYTpzcw=="),
+            Some(("user".to_string(), "pa:ss".to_string()))
+        );
+        assert_eq!(
+            parse_bearer_token("Bearer abc.def-ghi=="),
+            Some("abc.def-ghi==")
+        );
+        assert_eq!(parse_bearer_token("Bearer has space"), None);
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(!constant_time_eq(b"same", b"sam"));
+        assert_eq!(
+            parse_auth_params(r#"realm="a \"b\"", nc=00000001 ,qop=auth"#),
+            HashMap::from([
+                ("realm".to_string(), "a \"b\"".to_string()),
+                ("nc".to_string(), "00000001".to_string()),
+                ("qop".to_string(), "auth".to_string()),
+            ])
+        );
+    }
+}
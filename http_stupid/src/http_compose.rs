@@ -1,136 +1,727 @@
 use std::{fs::File, str};
 
-use crate::*;
+use crate::{
+    http_error_page::apply_error_page, http_headers::HeaderMap, http_query::normalize_path, *,
+};
 
 const DISALLOWED_PATTERNS: [&str; 2] = ["..", "./"];
 
-/// Takes HTTP's server function and the parsed data from
+/// Directory files are served from, which is also where error page files are looked up.
+pub(crate) const DOCUMENT_ROOT: &str = "./";
+
+/// Takes the settings of the HTTP server and the parsed data from
 /// [`httpParser::parse_http_connection()`], depending on the server type spits out the
 /// headers in the request when server_function is Debug, or gets the file requested if
 /// server_function is ServeFile, if function is ServeFile also makes sure it is not attempting to
-/// do a file path escape.
+/// do a file path escape. If compression is enabled the body is then compressed with whatever
+/// coding the client prefers. HEAD requests are composed exactly like a GET request, but only the
+/// status and headers of the response are returned. OPTIONS requests are answered automatically
+/// with the methods allowed on the path, and requests using any other method which is not allowed
+/// get a 405 with the same `Allow` header. When CORS is enabled the CORS headers for the request
+/// are added to whatever response ends up being sent, including the preflight response.
+///
+/// The middleware of the server wraps all of this, see [`http_middleware::HttpMiddleware`] for
+/// the order they are called in.
 pub fn compose_http_response(
-    http_server_function: ServerFunction,
-    http_keep_alive: bool,
-    parse_return_data: ParseReturnData,
+    http_server_settings: &HttpServerSettings,
+    mut parse_return_data: ParseReturnData,
 ) -> Vec<u8> {
-    if ServerFunction::Debug == http_server_function
-        || http_server_function == ServerFunction::DumpRequest
-    {
-        let mut http_response_struct = HttpResponseStruct::new();
+    let mut middleware_ran: usize = 0;
+    let mut short_circuit: Option<HttpResponseStruct> = None;
 
-        http_response_struct.set_status(200);
+    for middleware in &http_server_settings.middleware {
+        middleware_ran += 1;
+
+        if let MiddlewareAction::Respond(response) =
+            middleware.before_request(&mut parse_return_data)
+        {
+            short_circuit = Some(response);
+            break;
+        }
+    }
 
-        let mut response_body: String = "<html>".to_string();
+    let accept_encoding = parse_return_data.headers.get_combined("Accept-Encoding");
 
-        for i in parse_return_data.headers {
-            let header = format!(
-                "Header Name: {} <br/>Header Content: {} <br/><br/>",
-                i.0, i.1
-            );
+    let is_head_request = parse_return_data.http_request_type == HttpRequestType::HEAD;
+    if is_head_request {
+        parse_return_data.http_request_type = HttpRequestType::GET;
+    }
 
-            response_body.push_str(&header);
+    let allowed_methods = allowed_methods(http_server_settings, &parse_return_data.request_path);
+
+    let cors_headers = match &http_server_settings.cors {
+        Some(cors) => cors_headers(cors, &parse_return_data, &allowed_methods),
+        None => HeaderMap::new(),
+    };
+
+    let mut response = match short_circuit {
+        Some(response) => response,
+        None => compose_function_response(
+            http_server_settings,
+            accept_encoding.as_deref(),
+            &allowed_methods,
+            &parse_return_data,
+        ),
+    };
+
+    apply_error_page(
+        http_server_settings
+            .error_pages
+            .get(&response.status().code()),
+        Some(&parse_return_data),
+        &mut response,
+    );
+
+    // The origin is echoed back from the request so it still goes through the header checks
+    for (name, value) in cors_headers.iter() {
+        if let Err(e) = response.append_header(name, value) {
+            debug!("Skipping CORS header {name}: {e:?}");
         }
+    }
 
-        response_body.push_str("<html/>");
+    if is_head_request {
+        parse_return_data.http_request_type = HttpRequestType::HEAD;
+    }
+
+    for middleware in http_server_settings.middleware[..middleware_ran]
+        .iter()
+        .rev()
+    {
+        middleware.after_response(&parse_return_data, &mut response);
+    }
 
-        http_response_struct.set_body(response_body);
-        http_response_struct.add_default_headers();
-        if http_keep_alive {
-            http_response_struct.add_header("Keep-Alive: 7s");
-        } else {
-            http_response_struct.add_header("Connection: close");
+    if let Some(compression) = &http_server_settings.compression {
+        compress_response(&mut response, accept_encoding.as_deref(), compression);
+    }
+
+    response.set_version(parse_return_data.http_version);
+    let headers = response.headers_mut();
+    if keep_alive(http_server_settings, &parse_return_data) {
+        if !parse_return_data.http_version.keeps_alive_by_default() {
+            headers.insert("Connection", "keep-alive");
         }
+        headers.insert(
+            "Keep-Alive",
+            format!(
+                "timeout={}",
+                http_server_settings.limits.idle_timeout.as_secs()
+            ),
+        );
+    } else {
+        headers.insert("Connection", "close");
+    }
 
-        http_response_struct.get_response()
-    } else if ServerFunction::ServeFile == http_server_function {
-        if HttpRequestType::GET != parse_return_data.http_request_type {
-            let mut response: HttpResponseStruct = HttpResponseStruct::new();
+    if is_head_request {
+        response.get_response_head()
+    } else {
+        response.get_response()
+    }
+}
 
-            response.add_default_headers();
+/// Works out if the connection stays open after the response to the request, which needs
+/// keepalive to be enabled on the server and the client to want it. HTTP/1.1 clients want it
+/// unless they send `Connection: close`, HTTP/1.0 clients only when they send
+/// `Connection: keep-alive`.
+pub fn keep_alive(
+    http_server_settings: &HttpServerSettings,
+    parse_return_data: &ParseReturnData,
+) -> bool {
+    let connection = &parse_return_data.headers;
+
+    http_server_settings.keepalive
+        && !connection.contains_token("Connection", "close")
+        && (parse_return_data.http_version.keeps_alive_by_default()
+            || connection.contains_token("Connection", "keep-alive"))
+}
 
-            response.set_status(405);
+/// Picks what answers the request, which is either a 403 for clients the route doesn't allow, the
+/// automatic OPTIONS and 405 responses, the handler of the route for the path, or the server
+/// function.
+fn compose_function_response(
+    http_server_settings: &HttpServerSettings,
+    accept_encoding: Option<&str>,
+    allowed_methods: &[HttpRequestType],
+    parse_return_data: &ParseReturnData,
+) -> HttpResponseStruct {
+    let route = http_server_settings
+        .routes
+        .iter()
+        .find(|r| r.path == parse_return_data.request_path);
+
+    let denied = route.is_some_and(|route| !route.allows(parse_return_data.client_address));
+
+    if denied {
+        debug!(
+            "{:?} is not allowed to access {}",
+            parse_return_data.client_address, parse_return_data.request_path
+        );
+        http_server_settings.counters.record(403);
+        compose_forbidden()
+    } else if parse_return_data.http_request_type == HttpRequestType::OPTIONS {
+        compose_options_response(allowed_methods)
+    } else if !is_implemented(http_server_settings, &parse_return_data.http_request_type) {
+        compose_not_implemented()
+    } else if !allowed_methods.contains(&parse_return_data.http_request_type) {
+        compose_method_not_allowed(allowed_methods)
+    } else if let Some(route) = route {
+        (route.handler)(parse_return_data)
+    } else {
+        match http_server_settings.server_function {
+            ServerFunction::Debug | ServerFunction::DumpRequest => {
+                compose_debug_response(parse_return_data)
+            }
+            ServerFunction::ServeFile => compose_serve_file_response(
+                http_server_settings,
+                accept_encoding,
+                parse_return_data,
+            ),
+            _ => todo!(),
+        }
+    }
+}
 
-            response.get_response()
-        } else {
-            let document_root = "./";
+/// Checks if the server knows the method at all, which for an extension method means a route
+/// was added with it. Methods it doesn't know get a 501 instead of a 405.
+fn is_implemented(
+    http_server_settings: &HttpServerSettings,
+    http_request_type: &HttpRequestType,
+) -> bool {
+    match http_request_type {
+        HttpRequestType::Extension(_) => http_server_settings
+            .routes
+            .iter()
+            .any(|route| route.methods.contains(http_request_type)),
+        _ => true,
+    }
+}
 
-            let mut path = document_root.to_string();
+/// Returns the methods which can be used on the path, for a route these are the methods it was
+/// added with and otherwise they depend on the server function. HEAD is included whenever GET is,
+/// and OPTIONS is always included.
+pub fn allowed_methods(
+    http_server_settings: &HttpServerSettings,
+    request_path: &str,
+) -> Vec<HttpRequestType> {
+    let mut methods: Vec<HttpRequestType> = match http_server_settings
+        .routes
+        .iter()
+        .find(|r| r.path == request_path)
+    {
+        Some(route) => route.methods.clone(),
+        None => match http_server_settings.server_function {
+            ServerFunction::ServeFile => vec![HttpRequestType::GET],
+            _ => vec![
+                HttpRequestType::GET,
+                HttpRequestType::POST,
+                HttpRequestType::PUT,
+                HttpRequestType::PATCH,
+                HttpRequestType::DELETE,
+                HttpRequestType::TRACE,
+                HttpRequestType::CONNECT,
+            ],
+        },
+    };
+
+    if methods.contains(&HttpRequestType::GET) && !methods.contains(&HttpRequestType::HEAD) {
+        let position = methods
+            .iter()
+            .position(|m| *m == HttpRequestType::GET)
+            .unwrap();
+        methods.insert(position + 1, HttpRequestType::HEAD);
+    }
+    if !methods.contains(&HttpRequestType::OPTIONS) {
+        methods.push(HttpRequestType::OPTIONS);
+    }
 
-            let path_given = &parse_return_data.request_path[1..];
+    methods
+}
 
-            let mut contains_prohibited = false;
+/// Formats the methods given into the value of an `Allow` header.
+fn allow_header(allowed_methods: &[HttpRequestType]) -> String {
+    let methods: Vec<&str> = allowed_methods.iter().map(|m| m.as_str()).collect();
 
-            for i in DISALLOWED_PATTERNS {
-                if path_given.contains(i) {
-                    contains_prohibited = true;
-                }
-            }
+    methods.join(", ")
+}
 
-            path.push_str(path_given);
+fn compose_options_response(allowed_methods: &[HttpRequestType]) -> HttpResponseStruct {
+    let mut response = HttpResponseStruct::new();
 
-            if contains_prohibited {
-                let mut response: HttpResponseStruct = HttpResponseStruct::new();
+    response.set_status(204);
+    response.add_default_headers();
+    response
+        .headers_mut()
+        .insert("Allow", allow_header(allowed_methods));
 
-                response.add_default_headers();
+    response
+}
+
+fn compose_not_implemented() -> HttpResponseStruct {
+    let mut response = HttpResponseStruct::new();
+
+    response.set_status(501);
+    response.add_default_headers();
+
+    response
+}
+
+fn compose_forbidden() -> HttpResponseStruct {
+    let mut response = HttpResponseStruct::new();
+
+    response.set_status(403);
+    response.add_default_headers();
+
+    response
+}
+
+fn compose_method_not_allowed(allowed_methods: &[HttpRequestType]) -> HttpResponseStruct {
+    let mut response = HttpResponseStruct::new();
+
+    response.set_status(405);
+    response.add_default_headers();
+    response
+        .headers_mut()
+        .insert("Allow", allow_header(allowed_methods));
+
+    response
+}
 
-                response.set_status(403);
+fn compose_debug_response(parse_return_data: &ParseReturnData) -> HttpResponseStruct {
+    let mut http_response_struct = HttpResponseStruct::new();
+
+    http_response_struct.set_status(200);
+
+    let mut response_body: String = "<html>".to_string();
+
+    for i in parse_return_data.headers.iter() {
+        let header = format!(
+            "Header Name: {} <br/>Header Content: {} <br/><br/>",
+            i.0, i.1
+        );
+
+        response_body.push_str(&header);
+    }
+
+    response_body.push_str("<html/>");
+
+    http_response_struct.set_body(response_body);
+    http_response_struct.add_default_headers();
+
+    http_response_struct
+}
+
+fn compose_serve_file_response(
+    http_server_settings: &HttpServerSettings,
+    accept_encoding: Option<&str>,
+    parse_return_data: &ParseReturnData,
+) -> HttpResponseStruct {
+    let mut path = DOCUMENT_ROOT.to_string();
 
-                response.get_response()
-            } else {
-                let mut response: HttpResponseStruct = HttpResponseStruct::new();
+    // Paths from the parser are already normalized, this keeps it that way for any other caller
+    let normalized_path = normalize_path(&parse_return_data.request_path);
+    let path_given = &normalized_path[1..];
+
+    let mut contains_prohibited = false;
+
+    for i in DISALLOWED_PATTERNS {
+        if path_given.contains(i) {
+            contains_prohibited = true;
+        }
+    }
 
-                match File::open(path) {
-                    Ok(f) => {
-                        let mut buffer: String = String::new();
+    path.push_str(path_given);
 
-                        let mut buf_reader: BufReader<File> = BufReader::new(f);
+    if contains_prohibited {
+        let mut response: HttpResponseStruct = HttpResponseStruct::new();
 
-                        let read_status = buf_reader.read_to_string(&mut buffer);
+        response.add_default_headers();
 
-                        match read_status {
-                            Ok(_) => {
-                                response.set_body(buffer);
-                                response.set_status(200);
-                            }
-                            Err(_) => {
-                                debug!("File read failed");
-                                response.set_status(500)
-                            }
-                        };
+        response.set_status(403);
+
+        response
+    } else {
+        let mut response: HttpResponseStruct = HttpResponseStruct::new();
+
+        // If the client accepts gzip and there is a precompressed version next to the file
+        // we send that instead of compressing the file on every request.
+        let serve_precompressed = http_server_settings
+            .compression
+            .as_ref()
+            .is_some_and(|c| c.serve_precompressed)
+            && accept_encoding.is_some_and(|a| encoding_quality(a, ContentEncoding::Gzip) > 0.0);
+
+        if serve_precompressed {
+            if let Ok(f) = File::open(format!("{path}.gz")) {
+                let mut buffer: Vec<u8> = Vec::new();
+
+                if BufReader::new(f).read_to_end(&mut buffer).is_ok() {
+                    debug!("Serving precompressed file {path}.gz");
+                    response.set_body_bytes(buffer);
+                    response.set_status(200);
+                    response.add_default_headers();
+                    response.headers_mut().insert("Content-Encoding", "gzip");
+                    response.headers_mut().append("Vary", "Accept-Encoding");
+
+                    return response;
+                }
+            }
+        }
+
+        match File::open(path) {
+            Ok(f) => {
+                let mut buffer: String = String::new();
+
+                let mut buf_reader: BufReader<File> = BufReader::new(f);
+
+                let read_status = buf_reader.read_to_string(&mut buffer);
+
+                match read_status {
+                    Ok(_) => {
+                        response.set_body(buffer);
+                        response.set_status(200);
                     }
                     Err(_) => {
-                        debug!("File was not found");
-                        response.set_status(404)
+                        debug!("File read failed");
+                        response.set_status(500)
                     }
                 };
+            }
+            Err(_) => {
+                debug!("File was not found");
+                response.set_status(404)
+            }
+        };
 
-                response.add_default_headers();
+        response.add_default_headers();
 
-                response.get_response()
-            }
-        }
-    } else {
-        todo!()
+        response
     }
 }
 
 /// Composer designed to just return the most barebones that is needed to return a server error.
-pub fn compose_server_error() -> Vec<u8> {
+pub fn compose_server_error(http_server_settings: &HttpServerSettings) -> Vec<u8> {
+    compose_error(http_server_settings, 500)
+}
+
+/// Composes an error response for when there is no parsed request to answer, such as a request
+/// which was malformed, so only the error page for the status can go in it.
+pub fn compose_error(http_server_settings: &HttpServerSettings, status_code: u16) -> Vec<u8> {
+    let mut http_response_struct = HttpResponseStruct::new();
+
+    http_response_struct.set_status(status_code);
+
+    http_response_struct.add_default_headers();
+
+    apply_error_page(
+        http_server_settings.error_pages.get(&status_code),
+        None,
+        &mut http_response_struct,
+    );
+
+    http_response_struct.get_response()
+}
+
+/// Composes the 429 sent to a client over its rate or connection limit, which tells it how many
+/// seconds to wait before trying again.
+pub fn compose_too_many_requests(
+    http_server_settings: &HttpServerSettings,
+    retry_after: u64,
+) -> Vec<u8> {
     let mut http_response_struct = HttpResponseStruct::new();
 
-    http_response_struct.set_status(500);
+    http_response_struct.set_status(429);
 
     http_response_struct.add_default_headers();
+    http_response_struct
+        .headers_mut()
+        .insert("Retry-After", retry_after.to_string());
+
+    apply_error_page(
+        http_server_settings.error_pages.get(&429),
+        None,
+        &mut http_response_struct,
+    );
 
     http_response_struct.get_response()
 }
 
-/// Takes in the header as a Generic of a ref type of string, and converts it to bytes and appends
-/// it to the vector given and returns the vector which has the header applied to the Vector of
-/// bytes.
-pub fn add_header<T: AsRef<str>>(header: T, mut vector: Vec<u8>) -> Vec<u8> {
-    vector.extend_from_slice(header.as_ref().as_bytes());
+#[cfg(test)]
+mod http_compose_tests {
+    use crate::http_compose::*;
+    use crate::http_headers::HeaderMap;
+    use crate::http_ip_filter::IpFilter;
+    use crate::http_limits::RequestLimits;
+    use standard_stupid::find_substring_bytes_start;
+
+    fn request(http_request_type: HttpRequestType, request_path: &str) -> ParseReturnData {
+        ParseReturnData {
+            http_request_type,
+            request_target: request_path.to_string(),
+            request_path: request_path.to_string(),
+            headers: HeaderMap::from_iter([("Host", "127.0.0.1")]),
+            ..Default::default()
+        }
+    }
 
-    vector
+    fn settings(server_function: ServerFunction) -> HttpServerSettings {
+        HttpServerSettings {
+            server_function,
+            limits: RequestLimits {
+                max_body_size: 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn has_header(response: &[u8], header: &str) -> bool {
+        find_substring_bytes_start(response, format!("{header}\r\n").as_bytes()).is_ok()
+    }
+
+    #[test]
+    fn head_matches_get_without_body() {
+        for server_function in [ServerFunction::Debug, ServerFunction::ServeFile] {
+            let get = compose_http_response(
+                &settings(server_function),
+                request(HttpRequestType::GET, "/Cargo.toml"),
+            );
+            let head = compose_http_response(
+                &settings(server_function),
+                request(HttpRequestType::HEAD, "/Cargo.toml"),
+            );
+
+            let header_end = find_substring_bytes_start(&get, b"\r\n\r\n").unwrap() as usize + 4;
+
+            assert!(get.len() > header_end);
+            assert_eq!(head, get[..header_end]);
+        }
+    }
+
+    #[test]
+    fn options_lists_allowed_methods() {
+        let response = compose_http_response(
+            &settings(ServerFunction::ServeFile),
+            request(HttpRequestType::OPTIONS, "/Cargo.toml"),
+        );
+
+        assert!(response.starts_with(b"HTTP/1.1 204"));
+        assert!(has_header(&response, "Allow: GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn method_not_allowed_includes_allow() {
+        let response = compose_http_response(
+            &settings(ServerFunction::ServeFile),
+            request(HttpRequestType::POST, "/Cargo.toml"),
+        );
+
+        assert!(response.starts_with(b"HTTP/1.1 405"));
+        assert!(has_header(&response, "Allow: GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn routes_use_their_own_methods() {
+        let mut settings = settings(ServerFunction::ServeFile);
+        settings.routes.push(HttpRoute {
+            path: "/submit".to_string(),
+            methods: vec![HttpRequestType::POST],
+            handler: Arc::new(|_| {
+                let mut response = HttpResponseStruct::new();
+                response.set_status(201);
+                response
+            }),
+            ip_filter: None,
+            multipart: false,
+        });
+
+        let response = compose_http_response(&settings, request(HttpRequestType::POST, "/submit"));
+        assert!(response.starts_with(b"HTTP/1.1 201"));
+
+        let response = compose_http_response(&settings, request(HttpRequestType::GET, "/submit"));
+        assert!(response.starts_with(b"HTTP/1.1 405"));
+        assert!(has_header(&response, "Allow: POST, OPTIONS"));
+    }
+
+    #[test]
+    fn routes_refuse_clients_their_filter_denies() {
+        let mut settings = settings(ServerFunction::Debug);
+        settings.routes.push(HttpRoute {
+            path: "/admin".to_string(),
+            methods: vec![HttpRequestType::GET],
+            handler: Arc::new(|_| {
+                let mut response = HttpResponseStruct::new();
+                response.set_status(200);
+                response
+            }),
+            ip_filter: Some(IpFilter::default().allow("10.0.0.0/8").unwrap()),
+            multipart: false,
+        });
+
+        let from = |client_address: Option<&str>| {
+            let mut request = request(HttpRequestType::GET, "/admin");
+            request.client_address = client_address.map(|ip| ip.parse().unwrap());
+            compose_http_response(&settings, request)
+        };
+
+        assert!(from(Some("10.1.1.1")).starts_with(b"HTTP/1.1 200"));
+        assert!(from(Some("192.168.1.1")).starts_with(b"HTTP/1.1 403 Forbidden"));
+        assert!(from(None).starts_with(b"HTTP/1.1 403 Forbidden"));
+        // Other paths are not affected by the filter of the route
+        let mut request = request(HttpRequestType::GET, "/");
+        request.client_address = Some("192.168.1.1".parse().unwrap());
+        assert!(compose_http_response(&settings, request).starts_with(b"HTTP/1.1 200"));
+        assert_eq!(
+            settings
+                .counters
+                .denied
+                .load(std::sync::atomic::Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[test]
+    fn routes_can_echo_json() {
+        let mut settings = settings(ServerFunction::ServeFile);
+        settings.routes.push(HttpRoute {
+            path: "/echo".to_string(),
+            methods: vec![HttpRequestType::POST],
+            handler: Arc::new(|parse_return_data| {
+                let mut response = HttpResponseStruct::new();
+                response.set_status(200);
+                response.add_default_headers();
+                match parse_return_data.json() {
+                    Ok(value) => response.set_json(&value),
+                    Err(_) => response.set_status(400),
+                }
+                response
+            }),
+            ip_filter: None,
+            multipart: false,
+        });
+
+        let mut echo = request(HttpRequestType::POST, "/echo");
+        echo.headers
+            .insert("Content-Type".to_string(), "application/json".to_string());
+        echo.body = b"{ \"ok\" : [true] }".to_vec();
+
+        let response = compose_http_response(&settings, echo.clone());
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        assert!(has_header(&response, "Content-Type: application/json"));
+        assert!(!has_header(&response, "Content-Type: text/html"));
+        assert!(response.ends_with(b"\r\n\r\n{\"ok\":[true]}"));
+
+        echo.body = b"{ \"ok\" : [true, ] }".to_vec();
+        let response = compose_http_response(&settings, echo);
+        assert!(response.starts_with(b"HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn cors_preflight_is_answered() {
+        let mut settings = settings(ServerFunction::ServeFile);
+        settings.cors = Some(CorsConfig::default());
+
+        let mut preflight = request(HttpRequestType::OPTIONS, "/Cargo.toml");
+        preflight
+            .headers
+            .insert("Origin".to_string(), "https://app.example".to_string());
+        preflight.headers.insert(
+            "Access-Control-Request-Method".to_string(),
+            "GET".to_string(),
+        );
+
+        let response = compose_http_response(&settings, preflight);
+
+        assert!(response.starts_with(b"HTTP/1.1 204"));
+        assert!(has_header(&response, "Access-Control-Allow-Origin: *"));
+        assert!(has_header(
+            &response,
+            "Access-Control-Allow-Methods: GET, HEAD, OPTIONS"
+        ));
+    }
+
+    #[test]
+    fn errors_get_error_pages() {
+        let mut settings = settings(ServerFunction::ServeFile);
+
+        let response = compose_http_response(&settings, request(HttpRequestType::GET, "/missing"));
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+        assert!(has_header(
+            &response,
+            "Content-Type: text/html; charset=utf-8"
+        ));
+        assert!(find_substring_bytes_start(&response, b"<h1>404 Not Found</h1>").is_ok());
+
+        settings.error_pages.insert(
+            405,
+            ErrorPage::Handler(Arc::new(|_, _, response| {
+                response.set_body("Try GET");
+            })),
+        );
+        let response =
+            compose_http_response(&settings, request(HttpRequestType::POST, "/Cargo.toml"));
+        assert!(response.ends_with(b"\r\n\r\nTry GET"));
+        assert!(has_header(&response, "Allow: GET, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn http_1_0_closes_unless_asked_to_keep_alive() {
+        let mut settings = settings(ServerFunction::Debug);
+        settings.keepalive = true;
+
+        let mut old = request(HttpRequestType::GET, "/");
+        old.http_version = HttpVersion::Http10;
+        assert!(!keep_alive(&settings, &old));
+        let response = compose_http_response(&settings, old.clone());
+        assert!(response.starts_with(b"HTTP/1.0 200 OK\r\n"));
+        assert!(has_header(&response, "Connection: close"));
+
+        old.headers.append("Connection", "Keep-Alive");
+        assert!(keep_alive(&settings, &old));
+        let response = compose_http_response(&settings, old);
+        assert!(has_header(&response, "Connection: keep-alive"));
+        assert!(has_header(&response, "Keep-Alive: timeout=7"));
+
+        let mut new = request(HttpRequestType::GET, "/");
+        assert!(keep_alive(&settings, &new));
+        new.headers.append("Connection", "close");
+        assert!(!keep_alive(&settings, &new));
+
+        settings.keepalive = false;
+        assert!(!keep_alive(&settings, &request(HttpRequestType::GET, "/")));
+    }
+
+    #[test]
+    fn extension_methods_need_a_route() {
+        let mut settings = settings(ServerFunction::Debug);
+        let propfind = HttpRequestType::Extension("PROPFIND".to_string());
+
+        let response = compose_http_response(&settings, request(propfind.clone(), "/dav"));
+        assert!(response.starts_with(b"HTTP/1.1 501 Not Implemented"));
+
+        settings.routes.push(HttpRoute {
+            path: "/dav".to_string(),
+            methods: vec![propfind.clone(), HttpRequestType::PATCH],
+            handler: Arc::new(|request| {
+                let mut response = HttpResponseStruct::new();
+                response.set_status(207);
+                response.set_body(request.http_request_type.as_str());
+                response
+            }),
+            ip_filter: None,
+            multipart: false,
+        });
+
+        let response = compose_http_response(&settings, request(propfind.clone(), "/dav"));
+        assert!(response.starts_with(b"HTTP/1.1 207"));
+        assert!(response.ends_with(b"PROPFIND"));
+
+        let response = compose_http_response(&settings, request(HttpRequestType::PATCH, "/dav"));
+        assert!(response.ends_with(b"PATCH"));
+
+        let response = compose_http_response(&settings, request(propfind, "/other"));
+        assert!(response.starts_with(b"HTTP/1.1 405"));
+        assert!(has_header(
+            &response,
+            "Allow: GET, HEAD, POST, PUT, PATCH, DELETE, TRACE, CONNECT, OPTIONS"
+        ));
+    }
 }
@@ -0,0 +1,251 @@
+use standard_stupid::compression::{gzip_compress, zlib_compress};
+
+use crate::http_struct::HttpResponseStruct;
+
+/// Content-codings we are able to produce for a response body.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+    Identity,
+}
+
+impl ContentEncoding {
+    /// The token used for this coding inside of `Accept-Encoding` and `Content-Encoding`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Identity => "identity",
+        }
+    }
+}
+
+/// Settings for compressing response bodies, bodies smaller than `minimum_size` are sent as is as
+/// the headers would eat up whatever we save, and only bodies with a Content-Type in
+/// `content_types` get compressed (an entry like `text/*` matches every text type). When
+/// `serve_precompressed` is set ServeFile will send `file.gz` instead of `file` if it exists and
+/// the client accepts gzip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionConfig {
+    pub minimum_size: usize,
+    pub content_types: Vec<String>,
+    pub serve_precompressed: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            minimum_size: 1024,
+            content_types: vec![
+                "text/*".to_string(),
+                "application/javascript".to_string(),
+                "application/json".to_string(),
+                "application/xml".to_string(),
+                "image/svg+xml".to_string(),
+            ],
+            serve_precompressed: true,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Checks if the Content-Type given (parameters like `; charset=utf-8` are ignored) is inside
+    /// of the allowlist.
+    pub fn allows_content_type<T: AsRef<str>>(&self, content_type: T) -> bool {
+        let media_type = content_type
+            .as_ref()
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        self.content_types.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_suffix("/*") {
+                Some(prefix) => media_type
+                    .split_once('/')
+                    .is_some_and(|(main_type, _)| main_type == prefix),
+                None => media_type == allowed,
+            }
+        })
+    }
+}
+
+/// Returns the quality value the `Accept-Encoding` header gives to the coding, falling back to
+/// the `*` entry if the coding is not listed. `identity` is acceptable unless explicitly refused.
+pub fn encoding_quality<T: AsRef<str>>(accept_encoding: T, encoding: ContentEncoding) -> f32 {
+    let mut wildcard: Option<f32> = None;
+
+    for entry in accept_encoding.as_ref().split(',') {
+        let mut parameters = entry.split(';');
+        let coding = parameters
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .filter_map(|value| value.trim().parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+
+        let matches = match encoding {
+            ContentEncoding::Gzip => coding == "gzip" || coding == "x-gzip",
+            _ => coding == encoding.as_str(),
+        };
+
+        if matches {
+            return quality;
+        } else if coding == "*" {
+            wildcard = Some(quality);
+        }
+    }
+
+    match (wildcard, encoding) {
+        (Some(quality), _) => quality,
+        (None, ContentEncoding::Identity) => 1.0,
+        (None, _) => 0.0,
+    }
+}
+
+/// Picks the coding to use for a response from the client's `Accept-Encoding` header, the one
+/// with the highest quality wins and gzip is preferred over deflate when they are equal.
+pub fn negotiate_content_encoding<T: AsRef<str>>(accept_encoding: T) -> ContentEncoding {
+    let gzip = encoding_quality(accept_encoding.as_ref(), ContentEncoding::Gzip);
+    let deflate = encoding_quality(accept_encoding.as_ref(), ContentEncoding::Deflate);
+
+    if gzip > 0.0 && gzip >= deflate {
+        ContentEncoding::Gzip
+    } else if deflate > 0.0 {
+        ContentEncoding::Deflate
+    } else {
+        ContentEncoding::Identity
+    }
+}
+
+/// Compresses the body of the response if the client accepts a coding we support, the body is
+/// at least the minimum size, and the Content-Type is allowed. Responses which already have a
+/// Content-Encoding (such as precompressed files) are left alone.
+pub fn compress_response(
+    response: &mut HttpResponseStruct,
+    accept_encoding: Option<&str>,
+    config: &CompressionConfig,
+) {
+    if response.get_header("Content-Encoding").is_some()
+        || response.get_body().len() < config.minimum_size
+    {
+        return;
+    }
+
+    match response.get_header("Content-Type") {
+        Some(content_type) if config.allows_content_type(content_type) => {}
+        _ => return,
+    }
+
+    // The response depends on Accept-Encoding from here on, so caches need to know
+    response.headers_mut().append("Vary", "Accept-Encoding");
+
+    let encoding = accept_encoding
+        .map(negotiate_content_encoding)
+        .unwrap_or(ContentEncoding::Identity);
+
+    let compressed = match encoding {
+        ContentEncoding::Gzip => gzip_compress(response.get_body()),
+        ContentEncoding::Deflate => zlib_compress(response.get_body()),
+        ContentEncoding::Identity => return,
+    };
+
+    if compressed.len() < response.get_body().len() {
+        response.set_body_bytes(compressed);
+        response
+            .headers_mut()
+            .insert("Content-Encoding", encoding.as_str());
+    }
+}
+
+#[cfg(test)]
+mod http_compression_tests {
+    use crate::http_compression::*;
+    use standard_stupid::compression::{gzip_decompress, zlib_decompress};
+
+    fn html_response(body: &str) -> HttpResponseStruct {
+        let mut response = HttpResponseStruct::new();
+        response.set_status(200);
+        response.add_default_headers();
+        response.set_body(body);
+        response
+    }
+
+    #[test]
+    fn negotiates_highest_quality() {
+        assert_eq!(
+            negotiate_content_encoding("gzip, deflate, br"),
+            ContentEncoding::Gzip
+        );
+        assert_eq!(
+            negotiate_content_encoding("gzip;q=0.5, deflate"),
+            ContentEncoding::Deflate
+        );
+        assert_eq!(
+            negotiate_content_encoding("gzip;q=0, *"),
+            ContentEncoding::Deflate
+        );
+        assert_eq!(negotiate_content_encoding("br"), ContentEncoding::Identity);
+        assert_eq!(
+            negotiate_content_encoding("identity"),
+            ContentEncoding::Identity
+        );
+    }
+
+    #[test]
+    fn compresses_allowed_response() {
+        let body = "<p>Lorem ipsum dolor sit amet</p>".repeat(100);
+        let mut response = html_response(&body);
+
+        compress_response(&mut response, Some("gzip"), &CompressionConfig::default());
+
+        assert_eq!(response.get_header("content-encoding").unwrap(), "gzip");
+        assert_eq!(response.get_header("Vary").unwrap(), "Accept-Encoding");
+        assert_eq!(
+            gzip_decompress(response.get_body()).unwrap(),
+            body.as_bytes()
+        );
+
+        let mut response = html_response(&body);
+
+        compress_response(
+            &mut response,
+            Some("deflate"),
+            &CompressionConfig::default(),
+        );
+
+        assert_eq!(response.get_header("Content-Encoding").unwrap(), "deflate");
+        assert_eq!(
+            zlib_decompress(response.get_body()).unwrap(),
+            body.as_bytes()
+        );
+    }
+
+    #[test]
+    fn skips_small_and_disallowed_responses() {
+        let mut response = html_response("<p>tiny</p>");
+
+        compress_response(&mut response, Some("gzip"), &CompressionConfig::default());
+
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let config = CompressionConfig {
+            content_types: vec!["application/json".to_string()],
+            ..Default::default()
+        };
+        let mut response = html_response(&"<p>Lorem ipsum</p>".repeat(100));
+
+        compress_response(&mut response, Some("gzip"), &config);
+
+        assert_eq!(response.get_header("Content-Encoding"), None);
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    fmt::{self, Display},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use errors_stupid::{CookieError, StdStupidError};
+
+use crate::http_headers::is_token;
+
+/// The cookies a client sent in its `Cookie` header, in the order they were sent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    /// Parses a `Cookie` header such as `session=abc; theme="dark"`. Clients are not always
+    /// careful about what they send, so pairs with an invalid name are skipped instead of failing
+    /// the whole header, and a value wrapped in quotes has them removed.
+    pub fn parse(header: &str) -> Self {
+        let cookies = header
+            .split(';')
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+
+                is_token(name).then(|| (name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        CookieJar { cookies }
+    }
+
+    /// Gets the value of the first cookie with the name given.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(cookie_name, _)| cookie_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie to send to the client in a `Set-Cookie` header (RFC 6265), added to a response with
+/// [`crate::http_struct::HttpResponseStruct::add_cookie()`].
+///
+/// The name and value are checked when the cookie is created and the path and domain when they
+/// are set, so nothing which could break out of the header can end up in it.
+///
+/// ```
+/// use std::time::Duration;
+/// use http_stupid::http_cookie::{SameSite, SetCookie};
+///
+/// let cookie = SetCookie::new("session", "abc123")
+///     .unwrap()
+///     .path("/")
+///     .unwrap()
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+///
+/// assert_eq!(
+///     cookie.to_string(),
+///     "session=abc123; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Result<Self, StdStupidError> {
+        if !is_token(name) {
+            return Err(CookieError::new(format!("Invalid cookie name {name:?}")).into());
+        }
+        if !value.bytes().all(is_cookie_octet) {
+            return Err(CookieError::new(format!("Invalid cookie value {value:?}")).into());
+        }
+
+        Ok(SetCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    /// Creates a cookie which tells the client to forget the cookie with this name right away,
+    /// the path and domain have to match the ones the cookie was set with.
+    pub fn removal(name: &str) -> Result<Self, StdStupidError> {
+        Ok(SetCookie::new(name, "")?
+            .max_age(Duration::ZERO)
+            .expires(UNIX_EPOCH))
+    }
+
+    pub fn path(mut self, path: &str) -> Result<Self, StdStupidError> {
+        if !is_attribute_value(path) {
+            return Err(CookieError::new(format!("Invalid cookie path {path:?}")).into());
+        }
+        self.path = Some(path.to_string());
+        Ok(self)
+    }
+
+    pub fn domain(mut self, domain: &str) -> Result<Self, StdStupidError> {
+        let valid = !domain.is_empty()
+            && domain
+                .bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'.');
+        if !valid {
+            return Err(CookieError::new(format!("Invalid cookie domain {domain:?}")).into());
+        }
+        self.domain = Some(domain.to_string());
+        Ok(self)
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets the SameSite attribute, browsers ignore `SameSite=None` on cookies which aren't
+    /// Secure so that also makes the cookie Secure.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Formats the cookie the way it goes into the `Set-Cookie` header, without the header name.
+impl Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats the time as an HTTP date (IMF-fixdate), e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. Times
+/// before 1970 are written as the epoch.
+pub fn format_http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let days = seconds / 86400;
+    let seconds_of_day = seconds % 86400;
+
+    // Turns days since the epoch into a civil date, see Howard Hinnant's `civil_from_days`
+    let shifted = days as i64 + 719468;
+    let era = shifted / 146097;
+    let day_of_era = shifted - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Bytes allowed in a cookie value, which leaves out whitespace, `"`, `,`, `;` and `\`.
+fn is_cookie_octet(c: u8) -> bool {
+    matches!(c, 0x21 | 0x23..=0x2B | 0x2D..=0x3A | 0x3C..=0x5B | 0x5D..=0x7E)
+}
+
+/// Attribute values can be anything but control characters and `;`.
+fn is_attribute_value(text: &str) -> bool {
+    text.bytes().all(|c| (0x20..0x7F).contains(&c) && c != b';')
+}
+
+#[cfg(test)]
+mod http_cookie_tests {
+    use crate::http_cookie::*;
+
+    #[test]
+    fn parses_cookie_header() {
+        let jar = CookieJar::parse("session=abc123; theme=\"dark\";bad name=x; empty=; lang=en");
+
+        assert_eq!(jar.get("session"), Some("abc123"));
+        assert_eq!(jar.get("theme"), Some("dark"));
+        assert_eq!(jar.get("empty"), Some(""));
+        assert_eq!(jar.get("bad name"), None);
+        assert_eq!(jar.len(), 4);
+        assert_eq!(
+            jar.iter().map(|(name, _)| name).collect::<Vec<&str>>(),
+            vec!["session", "theme", "empty", "lang"]
+        );
+    }
+
+    #[test]
+    fn builds_set_cookie() {
+        let cookie = SetCookie::new("id", "a3fWa")
+            .unwrap()
+            .domain("example.com")
+            .unwrap()
+            .expires(UNIX_EPOCH + Duration::from_secs(784111777))
+            .same_site(SameSite::None);
+
+        assert_eq!(
+            cookie.to_string(),
+            "id=a3fWa; Domain=example.com; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Secure; SameSite=None"
+        );
+        assert_eq!(
+            SetCookie::removal("id").unwrap().to_string(),
+            "id=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_cookies() {
+        assert!(SetCookie::new("", "x").is_err());
+        assert!(SetCookie::new("a=b", "x").is_err());
+        assert!(SetCookie::new("name", "two words").is_err());
+        assert!(SetCookie::new("name", "x;\r\nSet-Cookie: evil=1").is_err());
+        assert!(SetCookie::new("name", "x")
+            .unwrap()
+            .path("/; Domain=evil")
+            .is_err());
+        assert!(SetCookie::new("name", "x")
+            .unwrap()
+            .domain("example.com\r\n")
+            .is_err());
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+        assert_eq!(
+            format_http_date(UNIX_EPOCH + Duration::from_secs(4102444799)),
+            "Thu, 31 Dec 2099 23:59:59 GMT"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{
+    http_headers::HeaderMap,
+    http_struct::{HttpRequestType, ParseReturnData},
+};
+
+/// Settings for Cross-Origin Resource Sharing, origins are compared exactly (scheme, host and
+/// port, e.g. `https://example.com`) and `*` allows every origin. If `allowed_methods` is empty
+/// the methods allowed on the path are used, and an `allowed_headers` entry of `*` allows any
+/// request header. `max_age` is how many seconds a browser may cache the preflight for.
+///
+/// `allow_credentials` only applies to origins which are listed by name, origins which are only
+/// allowed through `*` get `Access-Control-Allow-Origin: *` without credentials.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<HttpRequestType>,
+    pub allowed_headers: Vec<String>,
+    pub exposed_headers: Vec<String>,
+    pub allow_credentials: bool,
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: Vec::new(),
+            allowed_headers: vec!["*".to_string()],
+            exposed_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: Some(600),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn allows_origin<T: AsRef<str>>(&self, origin: T) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin.as_ref())
+    }
+
+    pub fn allows_header<T: AsRef<str>>(&self, header: T) -> bool {
+        self.allowed_headers
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(header.as_ref()))
+    }
+}
+
+/// Works out which CORS headers should be added to the response for the request, they are
+/// appended to the response so a `Vary` header already on it is kept.
+///
+/// Requests without an `Origin` or with an origin which is not allowed get no CORS headers, so the
+/// browser will refuse to hand the response to the page. When origins are listed by name every
+/// response gets `Vary: Origin`, including those, so a shared cache doesn't hand one origin the
+/// headers meant for another. An OPTIONS request with an
+/// `Access-Control-Request-Method` is treated as a preflight and only gets the preflight headers
+/// if the method and every requested header are allowed.
+pub fn cors_headers(
+    cors_config: &CorsConfig,
+    parse_return_data: &ParseReturnData,
+    allowed_methods: &[HttpRequestType],
+) -> HeaderMap {
+    let mut refused = HeaderMap::new();
+    if cors_config.allowed_origins.iter().any(|o| o != "*") {
+        refused.append("Vary", "Origin");
+    }
+    let mut headers = refused.clone();
+
+    let origin = match parse_return_data.headers.get("Origin") {
+        Some(origin) if cors_config.allows_origin(origin) => origin,
+        _ => return refused,
+    };
+
+    let listed_origin = cors_config.allowed_origins.iter().any(|o| o == origin);
+
+    // Only origins which are listed by name are echoed and may send credentials, allowing them
+    // for `*` would let every site read the responses of logged in users
+    if listed_origin {
+        headers.append("Access-Control-Allow-Origin", origin);
+
+        if cors_config.allow_credentials {
+            headers.append("Access-Control-Allow-Credentials", "true");
+        }
+    } else {
+        headers.append("Access-Control-Allow-Origin", "*");
+    }
+
+    let preflight_method = parse_return_data
+        .headers
+        .get("Access-Control-Request-Method");
+
+    match preflight_method {
+        Some(method) if parse_return_data.http_request_type == HttpRequestType::OPTIONS => {
+            let methods = if cors_config.allowed_methods.is_empty() {
+                allowed_methods
+            } else {
+                &cors_config.allowed_methods
+            };
+
+            if !methods.iter().any(|m| m.as_str() == method.trim()) {
+                return refused;
+            }
+
+            let requested_headers: Vec<&str> = parse_return_data
+                .headers
+                .get("Access-Control-Request-Headers")
+                .map(|h| {
+                    h.split(',')
+                        .map(|header| header.trim())
+                        .filter(|header| !header.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if !requested_headers
+                .iter()
+                .all(|h| cors_config.allows_header(h))
+            {
+                return refused;
+            }
+
+            let method_names: Vec<&str> = methods.iter().map(|m| m.as_str()).collect();
+            headers.append("Access-Control-Allow-Methods", method_names.join(", "));
+
+            if !requested_headers.is_empty() {
+                headers.append("Access-Control-Allow-Headers", requested_headers.join(", "));
+            }
+
+            if let Some(max_age) = cors_config.max_age {
+                headers.append("Access-Control-Max-Age", max_age.to_string());
+            }
+        }
+        _ => {
+            if !cors_config.exposed_headers.is_empty() {
+                headers.append(
+                    "Access-Control-Expose-Headers",
+                    cors_config.exposed_headers.join(", "),
+                );
+            }
+        }
+    }
+
+    headers
+}
+
+#[cfg(test)]
+mod http_cors_tests {
+    use crate::http_cors::*;
+
+    const ALLOWED: [HttpRequestType; 3] = [
+        HttpRequestType::GET,
+        HttpRequestType::HEAD,
+        HttpRequestType::OPTIONS,
+    ];
+
+    fn request(http_request_type: HttpRequestType, headers: &[(&str, &str)]) -> ParseReturnData {
+        ParseReturnData {
+            http_request_type,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HeaderMap>(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn preflight_for_allowed_origin() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://app.example".to_string()],
+            allowed_headers: vec!["Content-Type".to_string()],
+            ..Default::default()
+        };
+
+        let headers = cors_headers(
+            &config,
+            &request(
+                HttpRequestType::OPTIONS,
+                &[
+                    ("Origin", "https://app.example"),
+                    ("Access-Control-Request-Method", "GET"),
+                    ("Access-Control-Request-Headers", "content-type"),
+                ],
+            ),
+            &ALLOWED,
+        );
+
+        assert_eq!(
+            headers.iter().collect::<Vec<(&str, &str)>>(),
+            vec![
+                ("Vary", "Origin"),
+                ("Access-Control-Allow-Origin", "https://app.example"),
+                ("Access-Control-Allow-Methods", "GET, HEAD, OPTIONS"),
+                ("Access-Control-Allow-Headers", "content-type"),
+                ("Access-Control-Max-Age", "600"),
+            ]
+        );
+    }
+
+    #[test]
+    fn preflight_rejects_method_and_headers() {
+        let config = CorsConfig {
+            allowed_headers: Vec::new(),
+            ..Default::default()
+        };
+
+        let bad_method = request(
+            HttpRequestType::OPTIONS,
+            &[
+                ("Origin", "https://app.example"),
+                ("Access-Control-Request-Method", "DELETE"),
+            ],
+        );
+        assert!(cors_headers(&config, &bad_method, &ALLOWED).is_empty());
+
+        let bad_header = request(
+            HttpRequestType::OPTIONS,
+            &[
+                ("Origin", "https://app.example"),
+                ("Access-Control-Request-Method", "GET"),
+                ("Access-Control-Request-Headers", "X-Secret"),
+            ],
+        );
+        assert!(cors_headers(&config, &bad_header, &ALLOWED).is_empty());
+    }
+
+    #[test]
+    fn simple_requests_get_decorated() {
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allow_credentials: true,
+            exposed_headers: vec!["X-Request-Id".to_string()],
+            ..Default::default()
+        };
+
+        let headers = cors_headers(
+            &config,
+            &request(HttpRequestType::GET, &[("Origin", "http://other.example")]),
+            &ALLOWED,
+        );
+
+        assert_eq!(
+            headers.iter().collect::<Vec<(&str, &str)>>(),
+            vec![
+                ("Access-Control-Allow-Origin", "*"),
+                ("Access-Control-Expose-Headers", "X-Request-Id"),
+            ]
+        );
+
+        // Credentials are only allowed for origins which are listed by name
+        let config = CorsConfig {
+            allowed_origins: vec!["*".to_string(), "https://app.example".to_string()],
+            ..config
+        };
+        let headers = cors_headers(
+            &config,
+            &request(HttpRequestType::GET, &[("Origin", "https://app.example")]),
+            &ALLOWED,
+        );
+
+        assert_eq!(
+            headers.iter().collect::<Vec<(&str, &str)>>(),
+            vec![
+                ("Vary", "Origin"),
+                ("Access-Control-Allow-Origin", "https://app.example"),
+                ("Access-Control-Allow-Credentials", "true"),
+                ("Access-Control-Expose-Headers", "X-Request-Id"),
+            ]
+        );
+    }
+
+    #[test]
+    fn disallowed_origin_only_gets_vary() {
+        let config = CorsConfig {
+            allowed_origins: vec!["https://app.example".to_string()],
+            ..Default::default()
+        };
+        let vary_only = vec![("Vary", "Origin")];
+
+        let headers = cors_headers(
+            &config,
+            &request(HttpRequestType::GET, &[("Origin", "https://evil.example")]),
+            &ALLOWED,
+        );
+        assert_eq!(headers.iter().collect::<Vec<(&str, &str)>>(), vary_only);
+
+        let headers = cors_headers(&config, &request(HttpRequestType::GET, &[]), &ALLOWED);
+        assert_eq!(headers.iter().collect::<Vec<(&str, &str)>>(), vary_only);
+
+        // With only `*` the answer is the same for every origin
+        assert!(cors_headers(
+            &CorsConfig::default(),
+            &request(HttpRequestType::GET, &[]),
+            &ALLOWED
+        )
+        .is_empty());
+    }
+}
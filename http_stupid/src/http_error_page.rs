@@ -0,0 +1,267 @@
+use std::{fmt, fs, sync::Arc};
+
+use tracing::debug;
+
+use crate::{
+    http_compose::DOCUMENT_ROOT,
+    http_struct::{HttpResponseStruct, HttpStatusCode, ParseReturnData},
+};
+
+/// Function called to fill in the body of an error response, it gets the response with the status
+/// and headers already set and the request if it could be parsed. Requests which failed before
+/// they were parsed (such as a 400 for a malformed request) have no request.
+pub type ErrorPageHandler =
+    Arc<dyn Fn(HttpStatusCode, Option<&ParseReturnData>, &mut HttpResponseStruct) + Send + Sync>;
+
+/// What to send as the body of an error response with a certain status, set with
+/// [`crate::HttpServer::set_error_page()`].
+#[derive(Clone)]
+pub enum ErrorPage {
+    /// A file relative to the document root, the Content-Type is worked out from the extension.
+    File(String),
+    Handler(ErrorPageHandler),
+}
+
+impl fmt::Debug for ErrorPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorPage::File(path) => f.debug_tuple("File").field(path).finish(),
+            ErrorPage::Handler(_) => f.debug_tuple("Handler").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Gives an error response (4xx or 5xx) without a body the page configured for its status, or
+/// the built in page if there is none or the file can't be read. Responses which already have a
+/// body are left alone so handlers can still send their own errors.
+pub fn apply_error_page(
+    error_page: Option<&ErrorPage>,
+    parse_return_data: Option<&ParseReturnData>,
+    response: &mut HttpResponseStruct,
+) {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error()) || !response.get_body().is_empty() {
+        return;
+    }
+
+    match error_page {
+        Some(ErrorPage::Handler(handler)) => {
+            handler(status, parse_return_data, response);
+            return;
+        }
+        Some(ErrorPage::File(path)) => {
+            let path = format!("{DOCUMENT_ROOT}{}", path.trim_start_matches('/'));
+
+            match fs::read(&path) {
+                Ok(page) => {
+                    response
+                        .headers_mut()
+                        .insert("Content-Type", content_type(&path));
+                    response.set_body_bytes(page);
+                    return;
+                }
+                Err(e) => debug!("Error page {path} could not be read: {e}"),
+            }
+        }
+        None => {}
+    }
+
+    let accept = parse_return_data.and_then(|d| d.headers.get_combined("Accept"));
+    let reason = response.reason().to_string();
+    let title = match reason.as_str() {
+        "" => status.code().to_string(),
+        reason => format!("{} {reason}", status.code()),
+    };
+
+    if prefers_html(accept.as_deref()) {
+        let title = escape_html(&title);
+        response
+            .headers_mut()
+            .insert("Content-Type", "text/html; charset=utf-8");
+        response.set_body_bytes(format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<hr>\n<p>std-stupid-http</p>\n</body>\n</html>\n"
+        ));
+    } else {
+        response
+            .headers_mut()
+            .insert("Content-Type", "text/plain; charset=utf-8");
+        response.set_body_bytes(format!("{title}\n"));
+    }
+}
+
+/// Works out from the `Accept` header if the client would rather have HTML than plain text, HTML
+/// wins when there is no header or both are equally acceptable as that is what browsers get.
+fn prefers_html(accept: Option<&str>) -> bool {
+    match accept {
+        Some(accept) => {
+            media_type_quality(accept, "text/html") >= media_type_quality(accept, "text/plain")
+        }
+        None => true,
+    }
+}
+
+/// Returns the quality value the `Accept` header gives to the media type, the most specific
+/// range which matches is used so `text/html;q=0` refuses HTML even with `*/*` listed.
+fn media_type_quality(accept: &str, media_type: &str) -> f32 {
+    let (main_type, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+    let mut best: Option<(u8, f32)> = None;
+
+    for entry in accept.split(',') {
+        let mut parameters = entry.split(';');
+        let range = parameters
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        let quality = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .filter_map(|value| value.trim().parse::<f32>().ok())
+            .next()
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+
+        let specificity = if range == media_type {
+            3
+        } else if range.strip_suffix("/*") == Some(main_type) {
+            2
+        } else if range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+
+        if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+            best = Some((specificity, quality));
+        }
+    }
+
+    best.map(|(_, quality)| quality).unwrap_or(0.0)
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod http_error_page_tests {
+    use crate::http_error_page::*;
+    use crate::http_headers::HeaderMap;
+
+    fn request(accept: Option<&str>) -> ParseReturnData {
+        ParseReturnData {
+            request_target: "/missing".to_string(),
+            request_path: "/missing".to_string(),
+            headers: accept
+                .map(|accept| ("Accept", accept))
+                .into_iter()
+                .collect::<HeaderMap>(),
+            ..Default::default()
+        }
+    }
+
+    fn error_response(status: u16) -> HttpResponseStruct {
+        let mut response = HttpResponseStruct::new();
+        response.set_status(status);
+        response.add_default_headers();
+        response
+    }
+
+    #[test]
+    fn built_in_page_follows_accept() {
+        let mut response = error_response(404);
+        apply_error_page(
+            None,
+            Some(&request(Some("text/html,*/*;q=0.8"))),
+            &mut response,
+        );
+        assert_eq!(
+            response.get_header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(String::from_utf8_lossy(response.get_body()).contains("<h1>404 Not Found</h1>"));
+
+        let mut response = error_response(404);
+        apply_error_page(
+            None,
+            Some(&request(Some("text/plain, text/html;q=0.5"))),
+            &mut response,
+        );
+        assert_eq!(
+            response.get_header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.get_body(), b"404 Not Found\n");
+
+        let mut response = error_response(500);
+        apply_error_page(None, None, &mut response);
+        assert!(String::from_utf8_lossy(response.get_body())
+            .contains("<title>500 Internal Server Error</title>"));
+    }
+
+    #[test]
+    fn configured_pages_are_used() {
+        let handler = ErrorPage::Handler(Arc::new(|status, request, response| {
+            let path = request.map(|r| r.request_path.as_str()).unwrap_or("");
+            response.set_body(format!("{} at {path}", status.code()));
+        }));
+
+        let mut response = error_response(403);
+        apply_error_page(Some(&handler), Some(&request(None)), &mut response);
+        assert_eq!(response.get_body(), b"403 at /missing");
+
+        let mut response = error_response(404);
+        apply_error_page(
+            Some(&ErrorPage::File("/Cargo.toml".to_string())),
+            None,
+            &mut response,
+        );
+        assert!(response.get_body().starts_with(b"[package]"));
+        assert_eq!(
+            response.get_header("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        // A file which is missing falls back to the built in page
+        let mut response = error_response(404);
+        apply_error_page(
+            Some(&ErrorPage::File("missing.html".to_string())),
+            None,
+            &mut response,
+        );
+        assert!(response.get_body().starts_with(b"<!DOCTYPE html>"));
+    }
+
+    #[test]
+    fn leaves_other_responses_alone() {
+        let mut ok = error_response(200);
+        apply_error_page(None, None, &mut ok);
+        assert!(ok.get_body().is_empty());
+
+        let mut with_body = error_response(404);
+        with_body.set_body("custom");
+        apply_error_page(None, None, &mut with_body);
+        assert_eq!(with_body.get_body(), b"custom");
+    }
+
+    #[test]
+    fn picks_most_specific_media_range() {
+        assert_eq!(media_type_quality("text/*;q=0.3, */*", "text/html"), 0.3);
+        assert_eq!(media_type_quality("text/html;q=0, */*", "text/html"), 0.0);
+        assert_eq!(media_type_quality("application/json", "text/html"), 0.0);
+        assert!(!prefers_html(Some("text/html;q=0, */*")));
+        assert!(prefers_html(None));
+    }
+}
@@ -0,0 +1,180 @@
+/// Headers of a request or response. Names are compared without caring about case, every value
+/// a header was given is kept in the order it was added, and the names keep the case they were
+/// added with so they are written out the same way.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the first value of the header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(entry_name, _)| entry_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Gets every value of the header in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(entry_name, _)| entry_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Gets every value of the header joined with `, `, which is how a header sent more than once
+    /// has to be read for list based headers such as `Accept-Encoding`.
+    pub fn get_combined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+
+        (!values.is_empty()).then(|| values.join(", "))
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks if the comma separated list in the header contains the token, ignoring case, e.g.
+    /// `Connection: keep-alive, Upgrade` contains `upgrade`. Every value of the header is checked.
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Sets the header to only this value, removing any value it had before.
+    pub fn insert<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds the value to the header, keeping the values it already had.
+    pub fn append<N: Into<String>, V: Into<String>>(&mut self, name: N, value: V) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value of the header, returning them.
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        let mut removed: Vec<String> = Vec::new();
+
+        self.entries.retain(|(entry_name, value)| {
+            if entry_name.eq_ignore_ascii_case(name) {
+                removed.push(value.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Collecting appends every pair, so repeated headers keep all of their values.
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in iter {
+            header_map.append(name, value);
+        }
+        header_map
+    }
+}
+
+/// A token as used for header and cookie names (RFC 9110 section 5.6.2).
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c))
+}
+
+/// Checks a header value can be written out as is, control characters other than tab are
+/// refused so a value can't end the header early and inject its own headers (RFC 9110 section
+/// 5.5).
+pub(crate) fn is_field_value(text: &str) -> bool {
+    text.bytes().all(|c| c == b'\t' || !c.is_ascii_control())
+}
+
+#[cfg(test)]
+mod http_headers_tests {
+    use crate::http_headers::*;
+
+    #[test]
+    fn lookup_ignores_case() {
+        let headers = HeaderMap::from_iter([("content-TYPE", "text/plain")]);
+
+        assert_eq!(headers.get("Content-Type"), Some("text/plain"));
+        assert!(headers.contains_key("CONTENT-type"));
+        assert_eq!(headers.get("Content-Length"), None);
+    }
+
+    #[test]
+    fn keeps_every_value_in_order() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept-Encoding", "gzip");
+        headers.append("Host", "example.com");
+        headers.append("accept-encoding", "br");
+
+        assert_eq!(
+            headers.get_all("Accept-Encoding").collect::<Vec<&str>>(),
+            vec!["gzip", "br"]
+        );
+        assert_eq!(
+            headers.get_combined("Accept-Encoding").as_deref(),
+            Some("gzip, br")
+        );
+
+        headers.insert("ACCEPT-ENCODING", "identity");
+        assert_eq!(
+            headers.iter().collect::<Vec<(&str, &str)>>(),
+            vec![("Host", "example.com"), ("ACCEPT-ENCODING", "identity")]
+        );
+
+        assert_eq!(headers.remove("accept-encoding"), vec!["identity"]);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn matches_tokens_in_lists() {
+        let headers = HeaderMap::from_iter([
+            ("Connection", "keep-alive, Upgrade"),
+            ("Connection", "close"),
+        ]);
+
+        assert!(headers.contains_token("connection", "upgrade"));
+        assert!(headers.contains_token("Connection", "close"));
+        assert!(!headers.contains_token("Connection", "keep"));
+        assert!(!headers.contains_token("Upgrade", "websocket"));
+    }
+
+    #[test]
+    fn validates_names_and_values() {
+        assert!(is_token("X-Request-Id"));
+        assert!(!is_token("Bad Name"));
+        assert!(!is_token("Name:"));
+        assert!(is_field_value("text/html;\tcharset=utf-8"));
+        assert!(!is_field_value("x\r\nSet-Cookie: evil=1"));
+        assert!(!is_field_value("x\0"));
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use errors_stupid::{HttpServerError, StdStupidError};
+
+use crate::http_headers::HeaderMap;
+
+/// A range of IPv4 or IPv6 addresses written in CIDR notation such as `10.0.0.0/8` or
+/// `2001:db8::/32`, a single address without a prefix length is a range of just that address.
+/// IPv4 addresses mapped into IPv6 (`::ffff:10.0.0.1`) are matched as the IPv4 address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    /// Creates the range of addresses which share the first `prefix_length` bits with the
+    /// address, any bits after the prefix are cleared.
+    pub fn new(address: IpAddr, prefix_length: u8) -> Result<Self, StdStupidError> {
+        let address = address.to_canonical();
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        if prefix_length > bits {
+            return Err(HttpServerError::new(format!(
+                "Prefix length {prefix_length} is too long for {address}"
+            ))
+            .into());
+        }
+
+        let address = match address {
+            IpAddr::V4(v4) => Ipv4Addr::from(u32::from(v4) & mask_v4(prefix_length)).into(),
+            IpAddr::V6(v6) => Ipv6Addr::from(u128::from(v6) & mask_v6(prefix_length)).into(),
+        };
+
+        Ok(IpNetwork {
+            address,
+            prefix_length,
+        })
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+
+    pub fn prefix_length(&self) -> u8 {
+        self.prefix_length
+    }
+
+    /// Checks if the address is in the range, addresses of the other IP version never are.
+    pub fn contains(&self, ip_address: IpAddr) -> bool {
+        match (self.address, ip_address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                u32::from(ip) & mask_v4(self.prefix_length) == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                u128::from(ip) & mask_v6(self.prefix_length) == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = StdStupidError;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let network = network.trim();
+
+        match network.split_once('/') {
+            Some((address, prefix_length)) => {
+                let prefix_length = prefix_length.parse::<u8>().map_err(|_| {
+                    HttpServerError::new(format!("Invalid prefix length in {network:?}"))
+                })?;
+                IpNetwork::new(address.parse()?, prefix_length)
+            }
+            None => {
+                let address: IpAddr = network.parse()?;
+                let address = address.to_canonical();
+                let prefix_length = match address {
+                    IpAddr::V4(_) => 32,
+                    IpAddr::V6(_) => 128,
+                };
+                IpNetwork::new(address, prefix_length)
+            }
+        }
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_length)
+    }
+}
+
+fn mask_v4(prefix_length: u8) -> u32 {
+    u32::MAX
+        .checked_shl(32 - u32::from(prefix_length))
+        .unwrap_or(0)
+}
+
+fn mask_v6(prefix_length: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_length))
+        .unwrap_or(0)
+}
+
+/// Lists of address ranges which may or may not connect, an address in any `deny` range is
+/// refused and if there are `allow` ranges the address has to be in one of them.
+///
+/// ```
+/// use http_stupid::http_ip_filter::IpFilter;
+///
+/// let filter = IpFilter::default()
+///     .allow("10.0.0.0/8")?
+///     .allow("::1")?
+///     .deny("10.0.0.13")?;
+///
+/// assert!(filter.allows("10.1.2.3".parse()?));
+/// assert!(!filter.allows("10.0.0.13".parse()?));
+/// assert!(!filter.allows("192.168.0.1".parse()?));
+/// # Ok::<(), errors_stupid::StdStupidError>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IpFilter {
+    pub allow: Vec<IpNetwork>,
+    pub deny: Vec<IpNetwork>,
+}
+
+impl IpFilter {
+    /// Adds a range to the allow list.
+    pub fn allow(mut self, network: &str) -> Result<Self, StdStupidError> {
+        self.allow.push(network.parse()?);
+        Ok(self)
+    }
+
+    /// Adds a range to the deny list.
+    pub fn deny(mut self, network: &str) -> Result<Self, StdStupidError> {
+        self.deny.push(network.parse()?);
+        Ok(self)
+    }
+
+    pub fn allows(&self, ip_address: IpAddr) -> bool {
+        !self.deny.iter().any(|network| network.contains(ip_address))
+            && (self.allow.is_empty()
+                || self
+                    .allow
+                    .iter()
+                    .any(|network| network.contains(ip_address)))
+    }
+}
+
+/// Works out the address of the client which sent a request through the proxies in
+/// `trusted_proxies`, by following `X-Forwarded-For` from the right (the hop closest to us) for as
+/// long as the address it came from is a trusted proxy. Without trusted proxies, or when the peer
+/// is not one of them, the header is ignored as anyone could have sent it.
+pub fn forwarded_client_address(
+    peer_address: IpAddr,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpNetwork],
+) -> IpAddr {
+    let mut client_address = peer_address.to_canonical();
+
+    let forwarded_for = match headers.get_combined("X-Forwarded-For") {
+        Some(forwarded_for) if !trusted_proxies.is_empty() => forwarded_for,
+        _ => return client_address,
+    };
+
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted_proxies
+            .iter()
+            .any(|proxy| proxy.contains(client_address))
+        {
+            break;
+        }
+
+        match parse_hop(hop.trim()) {
+            Some(hop) => client_address = hop.to_canonical(),
+            // Whatever is left of a hop we can't read can't be trusted either
+            None => break,
+        }
+    }
+
+    client_address
+}
+
+/// Reads one entry of `X-Forwarded-For`, some proxies add the port (`[::1]:8080`) so that is
+/// allowed too.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|address| address.ip()))
+        .ok()
+}
+
+#[cfg(test)]
+mod http_ip_filter_tests {
+    use crate::http_ip_filter::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_cidr_ranges() {
+        let private: IpNetwork = "10.20.30.40/8".parse().unwrap();
+        assert_eq!(private.to_string(), "10.0.0.0/8");
+        assert!(private.contains(ip("10.255.0.1")));
+        assert!(private.contains(ip("::ffff:10.0.0.1")));
+        assert!(!private.contains(ip("11.0.0.1")));
+        assert!(!private.contains(ip("::1")));
+
+        let documentation: IpNetwork = "2001:db8::/32".parse().unwrap();
+        assert!(documentation.contains(ip("2001:db8:ffff::1")));
+        assert!(!documentation.contains(ip("2001:db9::1")));
+
+        let everything: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains(ip("203.0.113.9")));
+        let single: IpNetwork = "::1".parse().unwrap();
+        assert_eq!(single.prefix_length(), 128);
+        assert!(single.contains(ip("::1")));
+
+        for invalid in [
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/",
+            "example.com/8",
+            "10.0.0",
+        ] {
+            assert!(invalid.parse::<IpNetwork>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = IpFilter::default()
+            .allow("192.168.0.0/16")
+            .unwrap()
+            .deny("192.168.1.0/24")
+            .unwrap();
+        assert!(filter.allows(ip("192.168.2.1")));
+        assert!(!filter.allows(ip("192.168.1.1")));
+        assert!(!filter.allows(ip("127.0.0.1")));
+
+        let deny_only = IpFilter::default().deny("fe80::/10").unwrap();
+        assert!(deny_only.allows(ip("127.0.0.1")));
+        assert!(!deny_only.allows(ip("fe80::1")));
+    }
+
+    #[test]
+    fn follows_forwarded_for_through_trusted_proxies() {
+        let proxies: Vec<IpNetwork> = vec!["10.0.0.0/8".parse().unwrap()];
+        let headers: HeaderMap = [("X-Forwarded-For", "198.51.100.1, 203.0.113.7, 10.0.0.2")]
+            .into_iter()
+            .collect();
+
+        // The right most address which isn't a proxy is the client, anything left of it could
+        // have been made up by the client
+        assert_eq!(
+            forwarded_client_address(ip("10.0.0.1"), &headers, &proxies),
+            ip("203.0.113.7")
+        );
+        assert_eq!(
+            forwarded_client_address(ip("192.0.2.1"), &headers, &proxies),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            forwarded_client_address(ip("10.0.0.1"), &headers, &[]),
+            ip("10.0.0.1")
+        );
+
+        let headers: HeaderMap = [("X-Forwarded-For", "unknown, [2001:db8::1]:443")]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            forwarded_client_address(ip("10.0.0.1"), &headers, &proxies),
+            ip("2001:db8::1")
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use errors_stupid::{BadRequestError, StdStupidError};
+
+/// Limits on the size of requests and on how slowly they may be sent, so a client can't tie up a
+/// worker or make us buffer without end. Requests over a size limit get a 413, 414 or 431, and
+/// requests which are sent too slowly get a 408.
+///
+/// `header_timeout` is how long the request line and headers may take from their first byte,
+/// and bodies have to arrive at `min_body_rate` bytes per second or faster once the
+/// `body_grace_period` is over. Reads which get nothing for `idle_timeout` end the connection,
+/// which is also how long a kept alive connection waits for its next request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestLimits {
+    pub max_head_size: usize,
+    pub max_target_length: usize,
+    pub max_header_count: usize,
+    pub max_header_line_size: usize,
+    pub max_body_size: usize,
+    pub header_timeout: Duration,
+    pub min_body_rate: u64,
+    pub body_grace_period: Duration,
+    pub idle_timeout: Duration,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        RequestLimits {
+            max_head_size: 64 * 1024,
+            max_target_length: 8 * 1024,
+            max_header_count: 100,
+            max_header_line_size: 8 * 1024,
+            max_body_size: 1024 * 1024,
+            header_timeout: Duration::from_secs(10),
+            min_body_rate: 1024,
+            body_grace_period: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(7),
+        }
+    }
+}
+
+/// Keeps track of how long a body has been arriving for, failing once it has fallen behind the
+/// minimum rate of the limits.
+#[derive(Debug)]
+pub struct TransferDeadline {
+    started: Instant,
+    min_rate: u64,
+    grace_period: Duration,
+}
+
+impl TransferDeadline {
+    pub fn new(limits: &RequestLimits) -> Self {
+        TransferDeadline {
+            started: Instant::now(),
+            min_rate: limits.min_body_rate,
+            grace_period: limits.body_grace_period,
+        }
+    }
+
+    /// Checks `received` bytes arriving in the time since the transfer started is fast enough, a
+    /// minimum rate of 0 turns the check off.
+    pub fn check(&self, received: usize) -> Result<(), StdStupidError> {
+        if self.min_rate == 0 {
+            return Ok(());
+        }
+
+        let allowed = self.grace_period + Duration::from_secs(received as u64 / self.min_rate);
+        if self.started.elapsed() > allowed {
+            return Err(
+                BadRequestError::with_status("Request body is arriving too slowly", 408).into(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// If the error is a read which timed out, which depending on the platform is either of these.
+pub(crate) fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Turns a read which timed out into a 408, other errors are passed on as they are.
+pub(crate) fn timeout_to_request_timeout(error: io::Error) -> StdStupidError {
+    match is_timeout(&error) {
+        true => BadRequestError::with_status("Timed out reading the request", 408).into(),
+        false => error.into(),
+    }
+}
+
+/// How many requests were refused for breaking the limits, coming from an address which is not
+/// allowed, or being malformed since the server started, get these with [`crate::HttpServer::limit_counters()`].
+#[derive(Debug, Default)]
+pub struct LimitCounters {
+    /// 400, requests which could not be parsed, and any other status without its own counter
+    pub malformed: AtomicU64,
+    /// 408, requests which were sent too slowly
+    pub timed_out: AtomicU64,
+    /// 413, bodies over the size limit
+    pub body_too_large: AtomicU64,
+    /// 414, request targets over the length limit
+    pub target_too_long: AtomicU64,
+    /// 431, too many headers or headers which are too large
+    pub headers_too_large: AtomicU64,
+    /// 429, requests over the rate limit of the client
+    pub rate_limited: AtomicU64,
+    /// 429, connections over the connection limit of the address, see
+    /// [`LimitCounters::record_refused_connection()`]
+    pub connections_refused: AtomicU64,
+    /// 403, connections or requests from addresses which are not allowed
+    pub denied: AtomicU64,
+    /// 417, expectations other than `100-continue` and bodies a middleware refused
+    pub expectation_failed: AtomicU64,
+    /// 501, transfer codings the server doesn't understand
+    pub not_implemented: AtomicU64,
+    /// 505, HTTP versions the server doesn't speak
+    pub version_not_supported: AtomicU64,
+}
+
+impl LimitCounters {
+    /// Counts a request which was refused with the status given.
+    pub fn record(&self, status_code: u16) {
+        let counter = match status_code {
+            408 => &self.timed_out,
+            413 => &self.body_too_large,
+            414 => &self.target_too_long,
+            403 => &self.denied,
+            429 => &self.rate_limited,
+            431 => &self.headers_too_large,
+            417 => &self.expectation_failed,
+            501 => &self.not_implemented,
+            505 => &self.version_not_supported,
+            _ => &self.malformed,
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection which was refused for going over the connection limit of its address,
+    /// which gets a 429 as well but is kept apart from requests which were rate limited.
+    pub fn record_refused_connection(&self) {
+        self.connections_refused.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod http_limits_tests {
+    use crate::http_limits::*;
+
+    #[test]
+    fn slow_transfers_fail() {
+        let limits = RequestLimits {
+            min_body_rate: 10,
+            body_grace_period: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut deadline = TransferDeadline::new(&limits);
+        deadline.started -= Duration::from_secs(3);
+
+        assert!(deadline.check(40).is_ok());
+        assert!(matches!(
+            deadline.check(20),
+            Err(StdStupidError::BadRequest(BadRequestError {
+                status: 408,
+                ..
+            }))
+        ));
+
+        deadline.min_rate = 0;
+        assert!(deadline.check(0).is_ok());
+    }
+
+    #[test]
+    fn counts_refusals_by_status() {
+        let counters = LimitCounters::default();
+        counters.record(431);
+        counters.record(431);
+        counters.record(408);
+        counters.record(400);
+        counters.record(417);
+        counters.record(501);
+        counters.record(505);
+        counters.record(418);
+
+        assert_eq!(counters.headers_too_large.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.timed_out.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.malformed.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.expectation_failed.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.not_implemented.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.version_not_supported.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.body_too_large.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt::Debug;
+
+use crate::http_struct::{HttpResponseStruct, ParseReturnData};
+
+/// What should happen with a request after a middleware has looked at it.
+#[derive(Debug, PartialEq)]
+pub enum MiddlewareAction {
+    /// Hand the request to the next middleware, or compose the response if this was the last one
+    Continue,
+    /// Stop here and send this response, nothing after this middleware gets to see the request
+    Respond(HttpResponseStruct),
+}
+
+/// A layer which runs around [`crate::http_compose::compose_http_response()`], added to a server
+/// with [`crate::HttpServer::add_middleware()`].
+///
+/// Middleware is called like an onion, `before_request` is called on each middleware in the
+/// order they were added, then the response is composed, and then `after_response` is called in
+/// the reverse order. If a middleware short-circuits with [`MiddlewareAction::Respond`] the
+/// middleware after it are skipped, but every middleware whose `before_request` was called
+/// (including the one that responded) still gets its `after_response` called. CORS headers are
+/// added before `after_response` runs, and compression happens after all of it so middleware
+/// always sees the uncompressed body.
+pub trait HttpMiddleware: Debug + Send + Sync {
+    /// Called with the request before it gets answered, the request can be changed (e.g.
+    /// rewriting the path or adding headers) and everything after this sees the change.
+    fn before_request(&self, _parse_return_data: &mut ParseReturnData) -> MiddlewareAction {
+        MiddlewareAction::Continue
+    }
+
+    /// Called for every request with a body before any of it is read, returning false refuses the
+    /// body with a 417, so a client which sent `Expect: 100-continue` never sends it and nothing of
+    /// it is read (or spooled to disk) otherwise.
+    fn accepts_body(&self, _parse_return_data: &ParseReturnData) -> bool {
+        true
+    }
+
+    /// Called with the request and the response which is about to be sent, so headers can be
+    /// added or the response replaced.
+    fn after_response(
+        &self,
+        _parse_return_data: &ParseReturnData,
+        _response: &mut HttpResponseStruct,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod http_middleware_tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::http_compose::compose_http_response;
+    use crate::http_limits::RequestLimits;
+    use crate::http_middleware::*;
+    use crate::http_struct::*;
+    use standard_stupid::find_substring_bytes_start;
+
+    /// Writes down when it gets called so the order can be checked
+    #[derive(Debug)]
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        respond: bool,
+    }
+
+    impl HttpMiddleware for Recorder {
+        fn before_request(&self, _parse_return_data: &mut ParseReturnData) -> MiddlewareAction {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("before {}", self.name));
+
+            if self.respond {
+                let mut response = HttpResponseStruct::new();
+                response.set_status(418);
+                MiddlewareAction::Respond(response)
+            } else {
+                MiddlewareAction::Continue
+            }
+        }
+
+        fn after_response(
+            &self,
+            _parse_return_data: &ParseReturnData,
+            response: &mut HttpResponseStruct,
+        ) {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("after {}", self.name));
+            response.append_header("X-Middleware", self.name).unwrap();
+        }
+    }
+
+    #[derive(Debug)]
+    struct RewritePath;
+
+    impl HttpMiddleware for RewritePath {
+        fn before_request(&self, parse_return_data: &mut ParseReturnData) -> MiddlewareAction {
+            if parse_return_data.request_path == "/old" {
+                parse_return_data.request_path = "/new".to_string();
+            }
+            MiddlewareAction::Continue
+        }
+    }
+
+    fn request(request_path: &str) -> ParseReturnData {
+        ParseReturnData {
+            request_target: request_path.to_string(),
+            request_path: request_path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn settings(middleware: Vec<Arc<dyn HttpMiddleware>>) -> HttpServerSettings {
+        HttpServerSettings {
+            routes: vec![HttpRoute {
+                path: "/new".to_string(),
+                methods: vec![HttpRequestType::GET],
+                handler: Arc::new(|_| {
+                    let mut response = HttpResponseStruct::new();
+                    response.set_status(202);
+                    response
+                }),
+                ip_filter: None,
+                multipart: false,
+            }],
+            middleware,
+            limits: RequestLimits {
+                max_body_size: 1024,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn middleware_runs_in_onion_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let settings = settings(vec![
+            Arc::new(Recorder {
+                name: "outer",
+                calls: Arc::clone(&calls),
+                respond: false,
+            }),
+            Arc::new(Recorder {
+                name: "inner",
+                calls: Arc::clone(&calls),
+                respond: false,
+            }),
+        ]);
+
+        let response = compose_http_response(&settings, request("/"));
+
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec!["before outer", "before inner", "after inner", "after outer"]
+        );
+        let inner = find_substring_bytes_start(&response, b"X-Middleware: inner").unwrap();
+        let outer = find_substring_bytes_start(&response, b"X-Middleware: outer").unwrap();
+        assert!(inner < outer);
+    }
+
+    #[test]
+    fn middleware_can_short_circuit() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let settings = settings(vec![
+            Arc::new(Recorder {
+                name: "outer",
+                calls: Arc::clone(&calls),
+                respond: true,
+            }),
+            Arc::new(Recorder {
+                name: "inner",
+                calls: Arc::clone(&calls),
+                respond: false,
+            }),
+        ]);
+
+        let response = compose_http_response(&settings, request("/"));
+
+        assert!(response.starts_with(b"HTTP/1.1 418"));
+        assert_eq!(*calls.lock().unwrap(), vec!["before outer", "after outer"]);
+    }
+
+    #[test]
+    fn middleware_can_modify_request() {
+        let settings = settings(vec![Arc::new(RewritePath)]);
+
+        let response = compose_http_response(&settings, request("/old"));
+
+        assert!(response.starts_with(b"HTTP/1.1 202"));
+    }
+}
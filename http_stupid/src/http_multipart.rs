@@ -0,0 +1,565 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    str,
+    sync::Arc,
+};
+
+use errors_stupid::{BadRequestError, StdStupidError};
+use standard_stupid::{hashing::to_hex_string, random_bytes};
+use tracing::debug;
+
+use crate::{
+    http_headers::HeaderMap,
+    http_limits::RequestLimits,
+    http_parser::{body_framing, read_framed_body},
+    http_struct::{BodyFraming, HttpServerSettings, ParseReturnData},
+};
+
+/// The most the headers of a single part may be.
+const MAX_PART_HEADER_SIZE: usize = 8 * 1024;
+
+/// Limits on a `multipart/form-data` body. Parts larger than `spool_threshold` are written to a
+/// file inside of `spool_directory` while they are being received instead of being kept in memory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartLimits {
+    pub max_total_size: usize,
+    pub max_part_size: usize,
+    pub max_parts: usize,
+    pub spool_threshold: usize,
+    pub spool_directory: PathBuf,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        MultipartLimits {
+            max_total_size: 64 * 1024 * 1024,
+            max_part_size: 32 * 1024 * 1024,
+            max_parts: 100,
+            spool_threshold: 256 * 1024,
+            spool_directory: env::temp_dir(),
+        }
+    }
+}
+
+/// A part which was written to disk, the file is removed when the last reference to it is
+/// dropped so to keep it it has to be moved elsewhere with [`fs::rename()`] (or copied) first.
+#[derive(Debug, PartialEq)]
+pub struct SpooledFile {
+    path: PathBuf,
+}
+
+impl SpooledFile {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SpooledFile {
+    fn drop(&mut self) {
+        // It is fine if it is gone already, that just means it was moved
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PartContent {
+    Memory(Vec<u8>),
+    File(Arc<SpooledFile>),
+}
+
+/// A single part of a `multipart/form-data` body, `name` and `filename` come from its
+/// `Content-Disposition`. The filename is whatever the client sent, so it should never be used as
+/// a path without cleaning it up first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartPart {
+    pub headers: HeaderMap,
+    pub name: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: usize,
+    pub content: PartContent,
+}
+
+impl MultipartPart {
+    /// Gets the content of the part, reading it from disk if it was spooled.
+    pub fn bytes(&self) -> Result<Vec<u8>, StdStupidError> {
+        match &self.content {
+            PartContent::Memory(content) => Ok(content.clone()),
+            PartContent::File(file) => Ok(fs::read(file.path())?),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ParserState {
+    Preamble,
+    AfterBoundary,
+    Headers,
+    Body,
+    Epilogue,
+}
+
+/// The part currently being received, and where its content is going.
+#[derive(Debug)]
+struct PartBuilder {
+    part: MultipartPart,
+    file: Option<File>,
+}
+
+/// A parser which gets fed the body of a `multipart/form-data` request as it arrives, so large
+/// parts never have to be held in memory all at once.
+#[derive(Debug)]
+pub struct MultipartParser {
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    state: ParserState,
+    buffer: Vec<u8>,
+    total_size: usize,
+    current: Option<PartBuilder>,
+    parts: Vec<MultipartPart>,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: MultipartLimits) -> Self {
+        MultipartParser {
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            limits,
+            state: ParserState::Preamble,
+            // The first boundary doesn't need a line break before it, pretending there is one
+            // means it can be found the same way as every other boundary
+            buffer: b"\r\n".to_vec(),
+            total_size: 0,
+            current: None,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Feeds the next bit of the body to the parser.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), StdStupidError> {
+        self.total_size += data.len();
+        if self.total_size > self.limits.max_total_size {
+            return Err(
+                BadRequestError::with_status("Multipart body is larger than allowed", 413).into(),
+            );
+        }
+
+        self.buffer.extend_from_slice(data);
+        while self.step()? {}
+
+        Ok(())
+    }
+
+    /// Finishes parsing and returns the parts, failing if the closing boundary was never seen.
+    pub fn finish(self) -> Result<Vec<MultipartPart>, StdStupidError> {
+        if self.state != ParserState::Epilogue {
+            return Err(
+                BadRequestError::new("Multipart body ended before the closing boundary").into(),
+            );
+        }
+
+        Ok(self.parts)
+    }
+
+    /// Makes as much progress as it can with what is buffered, returning if it should be called
+    /// again.
+    fn step(&mut self) -> Result<bool, StdStupidError> {
+        match self.state {
+            ParserState::Preamble => match find_bytes(&self.buffer, &self.delimiter) {
+                Some(position) => {
+                    self.buffer.drain(..position + self.delimiter.len());
+                    self.state = ParserState::AfterBoundary;
+                    Ok(true)
+                }
+                None => {
+                    let keep = self.delimiter.len() - 1;
+                    self.buffer.drain(..self.buffer.len().saturating_sub(keep));
+                    Ok(false)
+                }
+            },
+            ParserState::AfterBoundary => {
+                if self.buffer.starts_with(b"--") {
+                    self.buffer.clear();
+                    self.state = ParserState::Epilogue;
+                    return Ok(true);
+                }
+
+                // The boundary line may be followed by whitespace before the line break
+                match find_bytes(&self.buffer, b"\r\n") {
+                    Some(position)
+                        if self.buffer[..position]
+                            .iter()
+                            .all(|c| *c == b' ' || *c == b'\t') =>
+                    {
+                        self.buffer.drain(..position + 2);
+                        self.state = ParserState::Headers;
+                        Ok(true)
+                    }
+                    None if self.buffer.len() < 2
+                        || (self.buffer.len() < 256
+                            && self
+                                .buffer
+                                .iter()
+                                .all(|c| matches!(c, b' ' | b'\t' | b'\r'))) =>
+                    {
+                        Ok(false)
+                    }
+                    _ => Err(BadRequestError::new("Multipart boundary line is malformed").into()),
+                }
+            }
+            ParserState::Headers => {
+                let header_end = if self.buffer.starts_with(b"\r\n") {
+                    Some((0, 2))
+                } else {
+                    find_bytes(&self.buffer, b"\r\n\r\n").map(|position| (position, position + 4))
+                };
+
+                match header_end {
+                    Some((end, consumed)) => {
+                        let headers = parse_part_headers(&self.buffer[..end])?;
+                        self.buffer.drain(..consumed);
+                        self.start_part(headers)?;
+                        self.state = ParserState::Body;
+                        Ok(true)
+                    }
+                    None if self.buffer.len() > MAX_PART_HEADER_SIZE => {
+                        Err(BadRequestError::new("Multipart part headers are too large").into())
+                    }
+                    None => Ok(false),
+                }
+            }
+            ParserState::Body => match find_bytes(&self.buffer, &self.delimiter) {
+                Some(position) => {
+                    let content: Vec<u8> = self.buffer.drain(..position).collect();
+                    self.buffer.drain(..self.delimiter.len());
+                    self.write_content(&content)?;
+                    self.finish_part()?;
+                    self.state = ParserState::AfterBoundary;
+                    Ok(true)
+                }
+                None => {
+                    // The start of the delimiter could be at the end of the buffer, so hold on to
+                    // enough bytes to find it once the rest arrives
+                    let keep = self.delimiter.len() - 1;
+                    let content: Vec<u8> = self
+                        .buffer
+                        .drain(..self.buffer.len().saturating_sub(keep))
+                        .collect();
+                    self.write_content(&content)?;
+                    Ok(false)
+                }
+            },
+            ParserState::Epilogue => {
+                self.buffer.clear();
+                Ok(false)
+            }
+        }
+    }
+
+    fn start_part(&mut self, headers: HeaderMap) -> Result<(), StdStupidError> {
+        if self.parts.len() >= self.limits.max_parts {
+            return Err(BadRequestError::new("Multipart body has more parts than allowed").into());
+        }
+
+        let disposition = headers
+            .get("Content-Disposition")
+            .map(parse_disposition)
+            .unwrap_or_default();
+        let content_type = headers.get("Content-Type").map(|value| value.to_string());
+
+        self.current = Some(PartBuilder {
+            part: MultipartPart {
+                name: disposition.get("name").cloned(),
+                filename: disposition.get("filename").cloned(),
+                content_type,
+                headers,
+                size: 0,
+                content: PartContent::Memory(Vec::new()),
+            },
+            file: None,
+        });
+
+        Ok(())
+    }
+
+    fn write_content(&mut self, content: &[u8]) -> Result<(), StdStupidError> {
+        let builder = self
+            .current
+            .as_mut()
+            .ok_or_else(|| BadRequestError::new("Multipart content outside of a part"))?;
+
+        builder.part.size += content.len();
+        if builder.part.size > self.limits.max_part_size {
+            return Err(
+                BadRequestError::with_status("Multipart part is larger than allowed", 413).into(),
+            );
+        }
+
+        if builder.file.is_none() && builder.part.size > self.limits.spool_threshold {
+            let path = self.limits.spool_directory.join(format!(
+                "std-stupid-upload-{}",
+                to_hex_string(&random_bytes(16)?)
+            ));
+            debug!("Spooling multipart part to {:?}", path);
+
+            // The spool directory is usually shared, so nobody else gets to read the upload
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?;
+            let spooled = Arc::new(SpooledFile { path });
+
+            if let PartContent::Memory(buffered) = &builder.part.content {
+                file.write_all(buffered)?;
+            }
+            builder.part.content = PartContent::File(spooled);
+            builder.file = Some(file);
+        }
+
+        match (&mut builder.file, &mut builder.part.content) {
+            (Some(file), _) => file.write_all(content)?,
+            (None, PartContent::Memory(buffered)) => buffered.extend_from_slice(content),
+            (None, PartContent::File(_)) => unreachable!("Spooled parts always have a file"),
+        }
+
+        Ok(())
+    }
+
+    fn finish_part(&mut self) -> Result<(), StdStupidError> {
+        if let Some(builder) = self.current.take() {
+            if let Some(mut file) = builder.file {
+                file.flush()?;
+            }
+            self.parts.push(builder.part);
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the boundary out of a `multipart/form-data` Content-Type, or None if the request isn't
+/// multipart.
+pub fn multipart_boundary(parse_return_data: &ParseReturnData) -> Option<String> {
+    let content_type = parse_return_data.headers.get("Content-Type")?;
+    let (media_type, parameters) = content_type.split_once(';')?;
+
+    if !media_type
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    parse_parameters(parameters)
+        .remove("boundary")
+        .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+}
+
+/// Gets the boundary of a `multipart/form-data` body which is going to be parsed into parts,
+/// which only happens for requests to a route with `multipart` set using one of its methods.
+pub fn route_multipart_boundary(
+    http_server_settings: &HttpServerSettings,
+    parse_return_data: &ParseReturnData,
+) -> Option<String> {
+    let route = http_server_settings
+        .routes
+        .iter()
+        .find(|r| r.path == parse_return_data.request_path)?;
+
+    if !route.multipart || !route.methods.contains(&parse_return_data.http_request_type) {
+        return None;
+    }
+
+    multipart_boundary(parse_return_data)
+}
+
+/// Reads a `multipart/form-data` body from the reader with whichever framing it was sent with,
+/// feeding it to a [`MultipartParser`] as it arrives. Like other bodies it has to arrive at the
+/// minimum rate of the request limits, or it gets a 408.
+pub fn read_multipart_body<R: BufRead>(
+    stream_reader: &mut R,
+    parse_return_data: &ParseReturnData,
+    boundary: &str,
+    limits: &MultipartLimits,
+    request_limits: &RequestLimits,
+) -> Result<Vec<MultipartPart>, StdStupidError> {
+    if body_framing(parse_return_data)? == BodyFraming::None {
+        return Ok(Vec::new());
+    }
+
+    let mut parser = MultipartParser::new(boundary, limits.clone());
+    read_framed_body(
+        stream_reader,
+        parse_return_data,
+        limits.max_total_size,
+        request_limits,
+        |data| parser.feed(data),
+    )?;
+
+    parser.finish()
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_part_headers(header_block: &[u8]) -> Result<HeaderMap, StdStupidError> {
+    let header_block = str::from_utf8(header_block)
+        .map_err(|_| BadRequestError::new("Multipart part headers are not valid UTF-8"))?;
+
+    let mut headers = HeaderMap::new();
+    for line in header_block.split("\r\n").filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| BadRequestError::new("Multipart part header has no colon"))?;
+        headers.append(name.trim(), value.trim());
+    }
+
+    Ok(headers)
+}
+
+/// Parses the parameters of a `Content-Disposition` such as `form-data; name="file"`.
+fn parse_disposition(value: &str) -> HashMap<String, String> {
+    match value.split_once(';') {
+        Some((_, parameters)) => parse_parameters(parameters),
+        None => HashMap::new(),
+    }
+}
+
+/// Parses `;` separated `name=value` parameters, where the value can be a quoted string with
+/// backslash escapes, into a map from the lowercased name to the value.
+fn parse_parameters(parameters: &str) -> HashMap<String, String> {
+    let mut parsed: HashMap<String, String> = HashMap::new();
+    let mut characters = parameters.chars().peekable();
+
+    loop {
+        while characters
+            .peek()
+            .is_some_and(|c| c.is_whitespace() || *c == ';')
+        {
+            characters.next();
+        }
+
+        let name: String = characters
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            break;
+        }
+
+        while characters.peek().is_some_and(|c| c.is_whitespace()) {
+            characters.next();
+        }
+
+        let mut value = String::new();
+        if characters.peek() == Some(&'"') {
+            characters.next();
+            while let Some(c) = characters.next() {
+                match c {
+                    '\\' => value.extend(characters.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = characters.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value = value.trim().to_string();
+        }
+
+        parsed.insert(name, value);
+    }
+
+    parsed
+}
+
+#[cfg(test)]
+mod http_multipart_tests {
+    use crate::http_multipart::*;
+
+    const BODY: &[u8] = b"preamble\r\n--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nBuild 12\r\n--XyZ\r\nContent-Disposition: form-data; name=\"artifact\"; filename=\"out; 1.bin\"\r\nContent-Type: application/octet-stream\r\n\r\n\x00\x01\r\n--X\r\n\x02\x03\r\n--XyZ--\r\nepilogue";
+
+    fn limits(spool_threshold: usize) -> MultipartLimits {
+        MultipartLimits {
+            spool_threshold,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_fields_and_files_fed_in_pieces() {
+        // Feeding a byte at a time makes every boundary land across feeds
+        let mut parser = MultipartParser::new("XyZ", limits(1024));
+        for byte in BODY {
+            parser.feed(&[*byte]).unwrap();
+        }
+        let parts = parser.finish().unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name.as_deref(), Some("title"));
+        assert_eq!(parts[0].bytes().unwrap(), b"Build 12");
+        assert_eq!(parts[1].filename.as_deref(), Some("out; 1.bin"));
+        assert_eq!(
+            parts[1].content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        assert_eq!(parts[1].bytes().unwrap(), b"\x00\x01\r\n--X\r\n\x02\x03");
+    }
+
+    #[test]
+    fn spools_large_parts_to_disk() {
+        let mut parser = MultipartParser::new("XyZ", limits(4));
+        parser.feed(BODY).unwrap();
+        let parts = parser.finish().unwrap();
+
+        let path = match &parts[1].content {
+            PartContent::File(file) => file.path().to_path_buf(),
+            PartContent::Memory(_) => panic!("Part should have been spooled"),
+        };
+        assert_eq!(fs::read(&path).unwrap(), b"\x00\x01\r\n--X\r\n\x02\x03");
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions())
+                & 0o777,
+            0o600
+        );
+
+        drop(parts);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn enforces_limits() {
+        let mut parser = MultipartParser::new(
+            "XyZ",
+            MultipartLimits {
+                max_part_size: 8,
+                ..Default::default()
+            },
+        );
+        assert!(parser.feed(BODY).is_err());
+
+        let mut parser = MultipartParser::new(
+            "XyZ",
+            MultipartLimits {
+                max_parts: 1,
+                ..Default::default()
+            },
+        );
+        assert!(parser.feed(BODY).is_err());
+
+        let mut parser = MultipartParser::new("XyZ", MultipartLimits::default());
+        parser.feed(&BODY[..40]).unwrap();
+        assert!(parser.finish().is_err());
+    }
+}
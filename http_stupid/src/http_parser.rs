@@ -1,127 +1,633 @@
-use crate::*;
-use errors_stupid::StdStupidError;
-use std::{collections::HashMap, str};
+use crate::{
+    http_headers::{is_field_value, is_token, HeaderMap},
+    http_limits::{is_timeout, timeout_to_request_timeout, RequestLimits, TransferDeadline},
+    http_query::split_request_target,
+    *,
+};
+use errors_stupid::{BadRequestError, StdStupidError};
+use std::{collections::HashMap, io::BufRead, str, time::Instant};
 
 use tracing::debug;
 
 /// Takes an argument of `&[u8]` with the data contained being that from a buffered reader on a TCPListerner and returns the data contained within including the httpVersion used, the type of request that was recieved, the path that was requested, and lastly a hash map of all the headers in a <String, String> format where the key is the header name and the content is the headers content inside of the Struct of [`httpStruct::ParseReturnData`]
+///
+/// Only the request line and headers are parsed, the body is left empty and is read separately
+/// with [`read_request_body()`] as its length is only known after the headers.
+///
+/// Parsing follows RFC 9112 strictly, anything which could be read in more than one way (and so
+/// could be read differently by a proxy in front of us) is rejected as a bad request:
+/// - Lines end with CRLF only, a bare CR or LF anywhere in the head is refused, and the head has
+///   to end at its first empty line.
+/// - The request line has to be exactly a method, the target and `HTTP/x.y` separated by single
+///   spaces.
+/// - Header lines are split on their first colon, the name has to be a token with nothing between
+///   it and the colon, and values can't contain control characters.
+/// - Lines starting with whitespace (obsolete line folding) are refused.
+/// - HTTP/1.1 requests need exactly one `Host`, and `Content-Length` sent more than once has to
+///   have the same value every time, and can only be digits.
+/// - A request with a `Transfer-Encoding` can't also have a `Content-Length`, and can't be
+///   HTTP/1.0. `chunked` is the only transfer coding understood, any other gets a 501.
+///
+/// A request target longer than the limits gets a 414, and more header lines than allowed or a
+/// header line which is too long gets a 431.
 pub fn parse_http_connection(
     connection_data_raw: &[u8],
+    request_limits: &RequestLimits,
 ) -> Result<ParseReturnData, StdStupidError> {
-    let mut header_hash_map: HashMap<String, String> = HashMap::new();
-
-    let mut http_version_given: Option<f32> = None;
-    let mut http_request_type_given: Option<HttpRequestType> = None;
-    let mut http_path_given: Option<String> = None;
-
-    let mut iterator = connection_data_raw.lines();
-
-    for (e, i) in iterator.by_ref().enumerate() {
-        let unwrapped = i?;
-
-        if e == 0 {
-            let mut rest: &[u8];
-            let http: &[u8];
-            let path: &[u8];
-            let version: &[u8];
-            // Find the slash and get the HTTP request type
-            (http, rest) = unwrapped
-                .as_bytes()
-                .split_at_checked(
-                    unwrapped
-                        .find('/')
-                        .ok_or_else(|| HttpServerError::new("Failed to find /"))?,
-                )
-                .ok_or_else(|| {
-                    HttpServerError::new("Failed to split string... should be impossible")
-                })?;
-            http_request_type_given = Some(parse_http_request_type(str::from_utf8(http)?));
-            (path, rest) = rest
-                .split_at_checked(
-                    rest.windows(1)
-                        .position(|c| matches!(c, b"H"))
-                        .ok_or_else(|| HttpServerError::new("Failed to find H"))?,
-                )
-                .ok_or_else(|| {
-                    HttpServerError::new("Failed to split string... should be impossible")
-                })?;
-
-            http_path_given = Some(str::from_utf8(path)?.trim().to_string());
-
-            (_, version) = rest
-                .split_at_checked(
-                    rest.windows(1)
-                        .position(|c| matches!(c, b"/"))
-                        .ok_or_else(|| HttpServerError::new("Failed to find /"))?
-                        + 1,
-                )
-                .ok_or_else(|| {
-                    HttpServerError::new("Failed to split string... should be impossible")
-                })?;
-
-            http_version_given = Some(str::from_utf8(version)?.parse::<f32>()?);
-        } else if unwrapped.is_empty() {
-            break;
-        } else {
-            let header: Vec<&str> = unwrapped.split(":").collect();
+    let head = str::from_utf8(connection_data_raw)
+        .map_err(|_| BadRequestError::new("Request line or headers are not valid UTF-8"))?;
+    let head = head
+        .strip_suffix("\r\n\r\n")
+        .ok_or_else(|| BadRequestError::new("Request headers don't end with an empty line"))?;
+    // Something else could take a bare CR or LF as the end of a line and so end the headers
+    // somewhere else than we do
+    if head.split("\r\n").any(|line| line.contains(['\r', '\n'])) {
+        return Err(BadRequestError::new("Request head contains a bare CR or LF").into());
+    }
+    let mut lines = head.split("\r\n");
 
-            let http_header_name = header[0].trim().to_string();
-            let http_header_content = header[1..].concat().trim().to_string();
+    let request_line = lines.next().unwrap_or_default();
+    let (http_request_type, request_target, http_version) =
+        parse_request_line(request_line, request_limits.max_target_length)?;
 
-            if !http_header_name.is_empty() && !http_header_name.is_empty() {
-                header_hash_map.insert(http_header_name, http_header_content);
-            }
+    let mut header_map = HeaderMap::new();
+    for (count, line) in lines.enumerate() {
+        if line.is_empty() {
+            return Err(BadRequestError::new("Request head continues after its empty line").into());
         }
-    }
-
-    let mut body: String = String::new();
-    for i in iterator {
-        if http_request_type_given == Some(HttpRequestType::POST)
-            && header_hash_map.contains_key("Content-Type")
-        {
-            let content = header_hash_map.get("Content-Type");
-            if content == Some(&"application/x-www-form-urlencoded".to_string()) {
-                debug!("Got a x-www-form-urlencoded post request, handling");
-                body = i?;
-            } else {
-                debug!("Unsupported post Content-Type");
-            }
+        if count >= request_limits.max_header_count {
+            return Err(BadRequestError::with_status("Too many header fields", 431).into());
+        }
+        if line.len() > request_limits.max_header_line_size {
+            return Err(BadRequestError::with_status("Header field is too large", 431).into());
         }
+
+        let (name, value) = parse_header_line(line)?;
+        header_map.append(name, value);
     }
 
     #[cfg(debug_assertions)]
     {
         debug!(
-            request_path = http_path_given, request_type = ?http_request_type_given, version = ?http_version_given
+            request_target, request_type = ?http_request_type, version = ?http_version
         );
         debug!("-----Headers Contents----- ");
-        for (header, content) in &header_hash_map {
+        for (header, content) in header_map.iter() {
             debug!("{header} = {content}");
         }
         debug!("-----Headers End----- ");
-        if !body.is_empty() {
-            debug!("\n-----Body Contents----- ");
-            for i in body.splitn(64, "&") {
-                debug!("{}", i);
-            }
-            debug!("\n-----Body ended-----");
+    }
+
+    let hosts = header_map.get_all("Host").count();
+    if hosts > 1 || (hosts == 0 && http_version == HttpVersion::Http11) {
+        return Err(BadRequestError::new("Request needs exactly one Host header").into());
+    }
+
+    let content_lengths = header_map
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+        .map(parse_content_length)
+        .collect::<Result<Vec<usize>, StdStupidError>>()?;
+    if content_lengths.windows(2).any(|pair| pair[0] != pair[1]) {
+        return Err(BadRequestError::new("Conflicting Content-Length headers").into());
+    }
+
+    if header_map.get("Transfer-Encoding").is_some() {
+        if !content_lengths.is_empty() {
+            return Err(BadRequestError::new(
+                "Request has both Transfer-Encoding and Content-Length",
+            )
+            .into());
+        }
+        if http_version == HttpVersion::Http10 {
+            return Err(
+                BadRequestError::new("Transfer-Encoding can't be used with HTTP/1.0").into(),
+            );
+        }
+
+        let transfer_codings = transfer_codings(&header_map);
+        if !(transfer_codings.is_empty() || transfer_codings == ["chunked"]) {
+            return Err(BadRequestError::with_status(
+                format!("Unsupported transfer coding {transfer_codings:?}"),
+                501,
+            )
+            .into());
         }
     }
 
+    let (request_path, query) = match target_form(&request_target) {
+        RequestTargetForm::Origin => split_request_target(&request_target)?,
+        RequestTargetForm::Absolute => {
+            let (authority, path) = split_absolute_target(&request_target)?;
+            // The authority in the target wins over whatever Host was sent (RFC 9112 3.2.2)
+            header_map.insert("Host", authority);
+            split_request_target(&path)?
+        }
+        RequestTargetForm::Authority if http_request_type == HttpRequestType::CONNECT => {
+            (request_target.clone(), HashMap::new())
+        }
+        RequestTargetForm::Asterisk if http_request_type == HttpRequestType::OPTIONS => {
+            (request_target.clone(), HashMap::new())
+        }
+        _ => {
+            return Err(BadRequestError::new(format!(
+                "Request target {request_target:?} can't be used with {}",
+                http_request_type.as_str()
+            ))
+            .into())
+        }
+    };
+
     Ok(ParseReturnData {
-        http_version: http_version_given
-            .ok_or_else(|| HttpServerError::new("HTTP Version of the connection was invalid"))?,
-        http_request_type: http_request_type_given.ok_or_else(|| {
-            HttpServerError::new("Request Type Version of the connection was invalid")
-        })?,
-        request_path: http_path_given.ok_or_else(|| {
-            HttpServerError::new("Request Path Version of the connection was invalid")
-        })?,
-        headers: header_hash_map,
-        body,
+        http_version,
+        http_request_type,
+        request_target,
+        request_path,
+        query,
+        headers: header_map,
+        body: Vec::new(),
+        parts: Vec::new(),
+        client_address: None,
     })
 }
 
+/// Splits the request line into the method, the request target and the version, checking each of
+/// them.
+fn parse_request_line(
+    request_line: &str,
+    max_target_length: usize,
+) -> Result<(HttpRequestType, String, HttpVersion), StdStupidError> {
+    let malformed = || BadRequestError::new(format!("Malformed request line {request_line:?}"));
+
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (
+            method,
+            target,
+            HttpVersion::parse(version).ok_or_else(malformed)?,
+        ),
+        // A request line without a version is an HTTP/0.9 simple request
+        (Some(method), Some(target), None, None) => (method, target, HttpVersion::Http09),
+        _ => return Err(malformed().into()),
+    };
+
+    if !is_token(method) {
+        return Err(malformed().into());
+    }
+    let http_request_type = parse_http_request_type(method);
+
+    if target.len() > max_target_length {
+        return Err(BadRequestError::with_status("Request target is too long", 414).into());
+    }
+    if target.is_empty() || !target.bytes().all(|c| c.is_ascii_graphic()) {
+        return Err(malformed().into());
+    }
+
+    // Only HTTP/1.x is spoken over a plain connection
+    if !version.is_supported() {
+        return Err(
+            BadRequestError::with_status(format!("{version} is not supported"), 505).into(),
+        );
+    }
+
+    Ok((http_request_type, target.to_string(), version))
+}
+
+/// Splits a header line on its first colon, returning the name and the value without the
+/// whitespace around it.
+fn parse_header_line(line: &str) -> Result<(&str, &str), StdStupidError> {
+    if line.starts_with([' ', '\t']) {
+        return Err(BadRequestError::new("Obsolete line folding in headers").into());
+    }
+
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| BadRequestError::new(format!("Header line without a colon {line:?}")))?;
+
+    if !is_token(name) {
+        return Err(BadRequestError::new(format!("Invalid header name {name:?}")).into());
+    }
+    if !is_field_value(value) {
+        return Err(BadRequestError::new(format!("Invalid value for header {name}")).into());
+    }
+
+    Ok((name, value.trim_matches([' ', '\t'])))
+}
+
+/// Works out which of the four forms the request target is written in (RFC 9112 3.2).
+pub(crate) fn target_form(request_target: &str) -> RequestTargetForm {
+    if request_target == "*" {
+        RequestTargetForm::Asterisk
+    } else if request_target.starts_with('/') {
+        RequestTargetForm::Origin
+    } else if request_target.contains("://") {
+        RequestTargetForm::Absolute
+    } else {
+        RequestTargetForm::Authority
+    }
+}
+
+/// Splits an absolute-form target such as `http://example.com:8080/a?b` into the authority and
+/// the path with its query, an empty path becomes `/`. Userinfo (`user@host`) is refused.
+fn split_absolute_target(request_target: &str) -> Result<(&str, String), StdStupidError> {
+    let invalid = || BadRequestError::new(format!("Invalid absolute target {request_target:?}"));
+
+    let (scheme, rest) = request_target.split_once("://").ok_or_else(invalid)?;
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || b"+-.".contains(&c));
+    if !valid_scheme {
+        return Err(invalid().into());
+    }
+
+    let path_start = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(path_start);
+    if authority.is_empty() || authority.contains('@') {
+        return Err(invalid().into());
+    }
+
+    match path.starts_with('/') {
+        true => Ok((authority, path.to_string())),
+        false => Ok((authority, format!("/{path}"))),
+    }
+}
+
+/// Reads from the reader until the empty line which ends the headers, returning the request line
+/// and headers including that empty line, and leaving anything after it (the body, or the next
+/// request) in the reader. Returns an empty vector if the connection was closed (or the read
+/// timed out) before anything was sent, and a head cut short by the connection closing is a bad
+/// request rather than something to answer.
+///
+/// Heads larger than the limits get a 431 (or a 414 while still in the request line), and a head
+/// which is still not complete once the header timeout has passed since its first byte, or which
+/// stops arriving until a read times out, gets a 408.
+pub fn read_request_head<R: BufRead>(
+    stream_reader: &mut R,
+    request_limits: &RequestLimits,
+) -> Result<Vec<u8>, StdStupidError> {
+    let mut head: Vec<u8> = Vec::new();
+    let mut started: Option<Instant> = None;
+
+    loop {
+        let buffer = match stream_reader.fill_buf() {
+            Ok(buffer) => buffer,
+            Err(e) if head.is_empty() && is_timeout(&e) => return Ok(head),
+            Err(e) => return Err(timeout_to_request_timeout(e)),
+        };
+        let amount = buffer.len();
+
+        if amount == 0 {
+            return match head.is_empty() {
+                true => Ok(head),
+                false => Err(BadRequestError::new(
+                    "Connection closed before the end of the request headers",
+                )
+                .into()),
+            };
+        }
+
+        if started.get_or_insert_with(Instant::now).elapsed() > request_limits.header_timeout {
+            return Err(BadRequestError::with_status(
+                "Request headers are arriving too slowly",
+                408,
+            )
+            .into());
+        }
+
+        // The end of the headers could be split over two reads, so look back a few bytes
+        let search_from = head.len().saturating_sub(3);
+        let previous_length = head.len();
+        head.extend_from_slice(buffer);
+
+        let end = head[search_from..]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map(|position| search_from + position + 4);
+
+        if end.unwrap_or(head.len()) > request_limits.max_head_size {
+            // Without the end of the request line it is the target which is too long
+            return Err(match head.windows(2).any(|window| window == b"\r\n") {
+                true => BadRequestError::with_status("Request headers are too large", 431),
+                false => BadRequestError::with_status("Request line is too long", 414),
+            }
+            .into());
+        }
+
+        match end {
+            Some(end) => {
+                stream_reader.consume(end - previous_length);
+                head.truncate(end);
+                return Ok(head);
+            }
+            None => stream_reader.consume(amount),
+        }
+    }
+}
+
+/// The transfer codings of the request in the order they were applied, leaving out `identity`
+/// as it doesn't change anything.
+fn transfer_codings(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect()
+}
+
+/// Works out how the body of the request is framed from its headers, which
+/// [`parse_http_connection()`] has already checked can only be read one way.
+pub fn body_framing(parse_return_data: &ParseReturnData) -> Result<BodyFraming, StdStupidError> {
+    if transfer_codings(&parse_return_data.headers) == ["chunked"] {
+        return Ok(BodyFraming::Chunked);
+    }
+
+    match parse_return_data.headers.get("Content-Length") {
+        // Every value was checked to be the same, so the first one is the length
+        Some(length) => parse_content_length(length.split(',').next().unwrap_or_default())
+            .map(BodyFraming::Length),
+        None => Ok(BodyFraming::None),
+    }
+}
+
+/// Parses one `Content-Length` value, which can only be digits (RFC 9112 6.3), so that a `+5` or
+/// `0x5` some other parser would refuse isn't taken as a length.
+fn parse_content_length(value: &str) -> Result<usize, StdStupidError> {
+    let value = value.trim_matches([' ', '\t']);
+    match !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        true => value.parse::<usize>().map_err(|_| {
+            BadRequestError::with_status("Content-Length is larger than can be read", 413).into()
+        }),
+        false => Err(BadRequestError::new("Content-Length is not a valid length").into()),
+    }
+}
+
+/// Reads the body of the request from the reader, using the `Content-Length` or chunked
+/// `Transfer-Encoding` the request was sent with. Requests without either have no body, bodies
+/// larger than the limits are rejected with a 413 (before anything is read if the length is known
+/// up front), and bodies sent slower than the minimum rate get a 408.
+pub fn read_request_body<R: BufRead>(
+    stream_reader: &mut R,
+    parse_return_data: &ParseReturnData,
+    request_limits: &RequestLimits,
+) -> Result<Vec<u8>, StdStupidError> {
+    let mut body = Vec::new();
+
+    read_framed_body(
+        stream_reader,
+        parse_return_data,
+        request_limits.max_body_size,
+        request_limits,
+        |data| {
+            body.extend_from_slice(data);
+            Ok(())
+        },
+    )?;
+
+    Ok(body)
+}
+
+/// Reads the body of the request as it arrives, handing the bytes to `on_data` with any chunked
+/// framing already taken off. Trailer fields after the last chunk are read and thrown away.
+pub(crate) fn read_framed_body<R, F>(
+    stream_reader: &mut R,
+    parse_return_data: &ParseReturnData,
+    max_size: usize,
+    request_limits: &RequestLimits,
+    mut on_data: F,
+) -> Result<(), StdStupidError>
+where
+    R: BufRead,
+    F: FnMut(&[u8]) -> Result<(), StdStupidError>,
+{
+    let too_large = || BadRequestError::with_status("Request body is larger than allowed", 413);
+    let deadline = TransferDeadline::new(request_limits);
+    let mut received = 0;
+
+    match body_framing(parse_return_data)? {
+        BodyFraming::None => Ok(()),
+        BodyFraming::Length(length) if length > max_size => Err(too_large().into()),
+        BodyFraming::Length(length) => read_body_bytes(
+            stream_reader,
+            length,
+            &deadline,
+            &mut received,
+            &mut on_data,
+        ),
+        BodyFraming::Chunked => {
+            let max_line = request_limits.max_header_line_size;
+
+            loop {
+                let line = read_chunk_line(stream_reader, max_line, &deadline, received)?;
+                let size = parse_chunk_size(&line)?;
+                if size == 0 {
+                    break;
+                }
+                if size > max_size - received {
+                    return Err(too_large().into());
+                }
+
+                read_body_bytes(stream_reader, size, &deadline, &mut received, &mut on_data)?;
+                if !read_chunk_line(stream_reader, 0, &deadline, received)?.is_empty() {
+                    return Err(BadRequestError::new("Chunk is longer than its size").into());
+                }
+            }
+
+            for count in 0.. {
+                let line = read_chunk_line(stream_reader, max_line, &deadline, received)?;
+                if line.is_empty() {
+                    break;
+                }
+                if count >= request_limits.max_header_count {
+                    return Err(BadRequestError::with_status("Too many trailer fields", 431).into());
+                }
+                parse_header_line(&line)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Reads `length` bytes of the body, handing them to `on_data` as they arrive.
+fn read_body_bytes<R, F>(
+    stream_reader: &mut R,
+    mut length: usize,
+    deadline: &TransferDeadline,
+    received: &mut usize,
+    on_data: &mut F,
+) -> Result<(), StdStupidError>
+where
+    R: BufRead,
+    F: FnMut(&[u8]) -> Result<(), StdStupidError>,
+{
+    while length > 0 {
+        let buffer = stream_reader
+            .fill_buf()
+            .map_err(timeout_to_request_timeout)?;
+        if buffer.is_empty() {
+            return Err(BadRequestError::new("Connection closed in the middle of the body").into());
+        }
+
+        let amount = buffer.len().min(length);
+        on_data(&buffer[..amount])?;
+        stream_reader.consume(amount);
+        length -= amount;
+        *received += amount;
+        deadline.check(*received)?;
+    }
+
+    Ok(())
+}
+
+/// Reads one line of a chunked body (a chunk size, the end of a chunk or a trailer field) and
+/// returns it without its CRLF. Lines longer than `max_length` are refused.
+fn read_chunk_line<R: BufRead>(
+    stream_reader: &mut R,
+    max_length: usize,
+    deadline: &TransferDeadline,
+    received: usize,
+) -> Result<String, StdStupidError> {
+    let mut line: Vec<u8> = Vec::new();
+
+    loop {
+        let buffer = stream_reader
+            .fill_buf()
+            .map_err(timeout_to_request_timeout)?;
+        if buffer.is_empty() {
+            return Err(BadRequestError::new("Connection closed in the middle of the body").into());
+        }
+
+        let (amount, complete) = match buffer.iter().position(|&c| c == b'\n') {
+            Some(position) => (position + 1, true),
+            None => (buffer.len(), false),
+        };
+        line.extend_from_slice(&buffer[..amount]);
+        stream_reader.consume(amount);
+        deadline.check(received)?;
+
+        if line.len() > max_length + 2 {
+            return Err(BadRequestError::new("Chunk line is too long").into());
+        }
+        if complete {
+            break;
+        }
+    }
+
+    let line = line
+        .strip_suffix(b"\r\n")
+        .ok_or_else(|| BadRequestError::new("Chunk line does not end with CRLF"))?;
+    String::from_utf8(line.to_vec())
+        .map_err(|_| BadRequestError::new("Chunk line is not valid UTF-8").into())
+}
+
+/// Gets the size out of a chunk size line, chunk extensions after a `;` are ignored.
+fn parse_chunk_size(line: &str) -> Result<usize, StdStupidError> {
+    let size = line
+        .split_once(';')
+        .map_or(line, |(size, _)| size)
+        .trim_end_matches([' ', '\t']);
+
+    if size.is_empty() || !size.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(BadRequestError::new(format!("Invalid chunk size {line:?}")).into());
+    }
+
+    // Only a size too large for a usize can fail once it is known to be hex digits
+    usize::from_str_radix(size, 16).map_err(|_| {
+        BadRequestError::with_status("Request body is larger than allowed", 413).into()
+    })
+}
+
+/// Decides what to do about the `Expect` header of a request before its body is read, returning
+/// true if a `100 Continue` has to be sent first. A body which is larger than the limits, from a
+/// client the route doesn't allow, or which a middleware refuses, is refused with a 413, 403 or
+/// 417 before the client sends it, as is any expectation other than `100-continue`. HTTP/1.0 clients don't know about expectations so the
+/// header is ignored for them.
+pub fn check_expectation(
+    http_server_settings: &HttpServerSettings,
+    parse_return_data: &ParseReturnData,
+) -> Result<bool, StdStupidError> {
+    let expect = match parse_return_data.headers.get("Expect") {
+        Some(expect) if parse_return_data.http_version != HttpVersion::Http10 => expect,
+        _ => return Ok(false),
+    };
+
+    if !expect.eq_ignore_ascii_case("100-continue") {
+        return Err(BadRequestError::with_status(
+            format!("Unsupported expectation {expect:?}"),
+            417,
+        )
+        .into());
+    }
+
+    let body_length = match body_framing(parse_return_data)? {
+        // Nothing is waiting to be sent, so there is nothing to continue
+        BodyFraming::None | BodyFraming::Length(0) => return Ok(false),
+        BodyFraming::Length(length) => Some(length),
+        // The size of a chunked body is only known once it has been read
+        BodyFraming::Chunked => None,
+    };
+
+    let body_limit = match route_multipart_boundary(http_server_settings, parse_return_data) {
+        Some(_) => http_server_settings.multipart.max_total_size,
+        None => http_server_settings.limits.max_body_size,
+    };
+    if body_length.is_some_and(|length| length > body_limit) {
+        return Err(
+            BadRequestError::with_status("Request body is larger than allowed", 413).into(),
+        );
+    }
+
+    check_body(http_server_settings, parse_return_data)?;
+
+    Ok(true)
+}
+
+/// Checks the body of the request may be read before any of it is (and possibly spooled to
+/// disk), whether or not the client asked first with `Expect: 100-continue`. A client the route
+/// doesn't allow gets a 403 and a body any middleware refuses a 417.
+pub fn check_body(
+    http_server_settings: &HttpServerSettings,
+    parse_return_data: &ParseReturnData,
+) -> Result<(), StdStupidError> {
+    if body_framing(parse_return_data)? == BodyFraming::None {
+        return Ok(());
+    }
+
+    let allowed = http_server_settings
+        .routes
+        .iter()
+        .find(|r| r.path == parse_return_data.request_path)
+        .is_none_or(|route| route.allows(parse_return_data.client_address));
+    if !allowed {
+        return Err(BadRequestError::with_status(
+            "Request body is not allowed from the client",
+            403,
+        )
+        .into());
+    }
+
+    check_body_accepted(http_server_settings, parse_return_data)
+}
+
+/// Asks every middleware if the body of the request may be read, see
+/// [`HttpMiddleware::accepts_body()`].
+fn check_body_accepted(
+    http_server_settings: &HttpServerSettings,
+    parse_return_data: &ParseReturnData,
+) -> Result<(), StdStupidError> {
+    if !http_server_settings
+        .middleware
+        .iter()
+        .all(|middleware| middleware.accepts_body(parse_return_data))
+    {
+        return Err(BadRequestError::with_status("Request body was refused", 417).into());
+    }
+
+    Ok(())
+}
+
 fn parse_http_request_type<T: AsRef<str>>(to_parse: T) -> HttpRequestType {
     match to_parse.as_ref().trim() {
         "GET" => HttpRequestType::GET,
@@ -132,6 +638,600 @@ fn parse_http_request_type<T: AsRef<str>>(to_parse: T) -> HttpRequestType {
         "DELETE" => HttpRequestType::DELETE,
         "TRACE" => HttpRequestType::TRACE,
         "OPTIONS" => HttpRequestType::OPTIONS,
-        _ => HttpRequestType::INVALID,
+        "PATCH" => HttpRequestType::PATCH,
+        method => HttpRequestType::Extension(method.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod http_parser_tests {
+    use std::{
+        io::{BufReader, Cursor},
+        sync::Arc,
+    };
+
+    use crate::http_parser::*;
+    use crate::http_query::FormLimits;
+
+    fn limits(max_body_size: usize) -> RequestLimits {
+        RequestLimits {
+            max_body_size,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reads_head_and_body_of_pipelined_requests() {
+        let requests = b"POST /form?x=1 HTTP/1.1\r\nHost: a\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 11\r\n\r\nname=a+b&c=GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        // A tiny buffer makes the end of the headers land across reads
+        let mut reader = BufReader::with_capacity(5, Cursor::new(&requests[..]));
+
+        let head = read_request_head(&mut reader, &RequestLimits::default()).unwrap();
+        let mut parse_return_data =
+            parse_http_connection(&head, &RequestLimits::default()).unwrap();
+        parse_return_data.body =
+            read_request_body(&mut reader, &parse_return_data, &limits(64)).unwrap();
+
+        assert_eq!(parse_return_data.request_path, "/form");
+        assert_eq!(parse_return_data.query_value("x"), Some("1"));
+        assert_eq!(
+            parse_return_data.form(&FormLimits::default()).unwrap()["name"],
+            vec!["a b"]
+        );
+
+        let next = parse_http_connection(
+            &read_request_head(&mut reader, &RequestLimits::default()).unwrap(),
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(next.request_target, "/");
+        assert!(read_request_head(&mut reader, &RequestLimits::default())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn rejects_body_over_limit() {
+        let mut reader = BufReader::new(Cursor::new(&b"0123456789"[..]));
+        let mut parse_return_data = parse_http_connection(
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        parse_return_data.headers.insert(
+            "Content-Type".to_string(),
+            "application/x-www-form-urlencoded".to_string(),
+        );
+
+        assert!(matches!(
+            read_request_body(&mut reader, &parse_return_data, &limits(9)),
+            Err(StdStupidError::BadRequest(_))
+        ));
+        assert!(parse_return_data.form(&FormLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn reads_chunked_bodies() {
+        let requests = b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n6;name=value\r\nhello \r\n5\r\nworld\r\n0\r\nX-Checksum: 1\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mut reader = BufReader::with_capacity(5, Cursor::new(&requests[..]));
+
+        let head = read_request_head(&mut reader, &RequestLimits::default()).unwrap();
+        let parse_return_data = parse_http_connection(&head, &RequestLimits::default()).unwrap();
+        assert_eq!(
+            body_framing(&parse_return_data).unwrap(),
+            BodyFraming::Chunked
+        );
+        assert_eq!(
+            read_request_body(&mut reader, &parse_return_data, &limits(64)).unwrap(),
+            b"hello world"
+        );
+
+        // The chunks were all read, so what follows is the next request and not part of the body
+        let next = parse_http_connection(
+            &read_request_head(&mut reader, &RequestLimits::default()).unwrap(),
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(next.request_target, "/");
+
+        let chunked = parse_http_connection(
+            b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        let status = |body: &[u8], max_body_size| {
+            let mut reader = BufReader::new(Cursor::new(body));
+            match read_request_body(&mut reader, &chunked, &limits(max_body_size)) {
+                Err(StdStupidError::BadRequest(e)) => Some(e.status),
+                _ => None,
+            }
+        };
+
+        assert_eq!(status(b"5\r\nhello\r\n0\r\n\r\n", 5), None);
+        assert_eq!(status(b"5\r\nhello\r\n1\r\n!\r\n0\r\n\r\n", 5), Some(413));
+        assert_eq!(status(b"fffffffffffffffffffff\r\n", 5), Some(413));
+        assert_eq!(status(b"-5\r\nhello\r\n0\r\n\r\n", 64), Some(400));
+        assert_eq!(status(b"3\r\nhello\r\n0\r\n\r\n", 64), Some(400));
+        assert_eq!(status(b"5\nhello\r\n0\r\n\r\n", 64), Some(400));
+        assert_eq!(status(b"5\r\nhello\r\n", 64), Some(400));
+    }
+
+    #[test]
+    fn rejects_ambiguous_transfer_encoding() {
+        let parse = |request: &[u8]| parse_http_connection(request, &RequestLimits::default());
+
+        assert_eq!(
+            error_status(parse(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\n")),
+            Some(400)
+        );
+        assert_eq!(
+            error_status(parse(
+                b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n"
+            )),
+            Some(400)
+        );
+        for coding in ["gzip", "gzip, chunked", "chunked, chunked", "chunked;x=1"] {
+            let request =
+                format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {coding}\r\n\r\n");
+            assert_eq!(
+                error_status(parse(request.as_bytes())),
+                Some(501),
+                "{coding}"
+            );
+        }
+
+        let identity =
+            parse(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: identity\r\n\r\n").unwrap();
+        assert_eq!(body_framing(&identity).unwrap(), BodyFraming::None);
+        let split = parse(b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: identity\r\nTransfer-Encoding: Chunked\r\n\r\n").unwrap();
+        assert_eq!(body_framing(&split).unwrap(), BodyFraming::Chunked);
+    }
+
+    fn error_status(result: Result<ParseReturnData, StdStupidError>) -> Option<u16> {
+        match result {
+            Err(StdStupidError::BadRequest(e)) => Some(e.status),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn classifies_malformed_requests() {
+        let long_target = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_target_length)
+        );
+
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET\r\n\r\n",
+                &RequestLimits::default()
+            )),
+            Some(400)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"G(T / HTTP/1.1\r\n\r\n",
+                &RequestLimits::default()
+            )),
+            Some(400)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET / HTTP/x\r\n\r\n",
+                &RequestLimits::default()
+            )),
+            Some(400)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET / HTTP/2.0\r\n\r\n",
+                &RequestLimits::default()
+            )),
+            Some(505)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                long_target.as_bytes(),
+                &RequestLimits::default()
+            )),
+            Some(414)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET /\r\n\r\n",
+                &RequestLimits::default()
+            )),
+            Some(505)
+        );
+        assert_eq!(
+            parse_http_connection(b"GET / HTTP/1.0\r\n\r\n", &RequestLimits::default())
+                .unwrap()
+                .http_version,
+            HttpVersion::Http10
+        );
+
+        let huge_header = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(RequestLimits::default().max_head_size)
+        );
+        let mut reader = BufReader::new(Cursor::new(huge_header.as_bytes()));
+        assert_eq!(
+            error_status(
+                read_request_head(&mut reader, &RequestLimits::default()).map(|_| unreachable!())
+            ),
+            Some(431)
+        );
+
+        let huge_line = format!(
+            "GET /{}",
+            "a".repeat(RequestLimits::default().max_head_size)
+        );
+        let mut reader = BufReader::new(Cursor::new(huge_line.as_bytes()));
+        assert_eq!(
+            error_status(
+                read_request_head(&mut reader, &RequestLimits::default()).map(|_| unreachable!())
+            ),
+            Some(414)
+        );
+    }
+
+    /// Gives the data it holds and then fails every read as if the socket timed out.
+    struct StalledReader(Cursor<Vec<u8>>);
+
+    impl std::io::Read for StalledReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(std::io::ErrorKind::WouldBlock.into()),
+                amount => Ok(amount),
+            }
+        }
+    }
+
+    #[test]
+    fn enforces_configured_limits() {
+        let limits = RequestLimits {
+            max_header_count: 2,
+            max_header_line_size: 16,
+            ..Default::default()
+        };
+
+        assert!(
+            parse_http_connection(b"GET / HTTP/1.1\r\nHost: a\r\nX: b\r\n\r\n", &limits).is_ok()
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET / HTTP/1.1\r\nHost: a\r\nX: b\r\nY: c\r\n\r\n",
+                &limits
+            )),
+            Some(431)
+        );
+        assert_eq!(
+            error_status(parse_http_connection(
+                b"GET / HTTP/1.1\r\nHost: aaaaaaaaaaaaaaaa\r\n\r\n",
+                &limits
+            )),
+            Some(431)
+        );
+
+        // Going quiet between requests just ends the connection, going quiet halfway is a 408
+        let mut reader = BufReader::new(StalledReader(Cursor::new(Vec::new())));
+        assert!(read_request_head(&mut reader, &limits).unwrap().is_empty());
+
+        let mut reader = BufReader::new(StalledReader(Cursor::new(b"GET / HTTP/1.1\r\n".to_vec())));
+        assert_eq!(
+            error_status(read_request_head(&mut reader, &limits).map(|_| unreachable!())),
+            Some(408)
+        );
+        let mut reader = BufReader::new(Cursor::new(&b"GET /partial HTTP/1.1\r\nHost: a\r\n"[..]));
+        assert_eq!(
+            error_status(read_request_head(&mut reader, &limits).map(|_| unreachable!())),
+            Some(400)
+        );
+
+        let parse_return_data = parse_http_connection(
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        let mut reader = BufReader::new(StalledReader(Cursor::new(b"01234".to_vec())));
+        assert!(matches!(
+            read_request_body(&mut reader, &parse_return_data, &limits),
+            Err(StdStupidError::BadRequest(BadRequestError {
+                status: 408,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn parses_request_line_and_headers_strictly() {
+        let parse_return_data = parse_http_connection(
+            b"GET /Home?q=H HTTP/1.1\r\nHost: 127.0.0.1:9182\r\nX-Time:  12:30:00 \r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(parse_return_data.request_path, "/Home");
+        assert_eq!(parse_return_data.query_value("q"), Some("H"));
+        assert_eq!(parse_return_data.http_version, HttpVersion::Http11);
+        assert_eq!(parse_return_data.target_form(), RequestTargetForm::Origin);
+        assert_eq!(
+            parse_return_data.headers.get("Host"),
+            Some("127.0.0.1:9182")
+        );
+        assert_eq!(parse_return_data.headers.get("x-time"), Some("12:30:00"));
+
+        for bad in [
+            &b"GET  / HTTP/1.1\r\nHost: a\r\n\r\n"[..],
+            b"GET / HTTP/1.1 \r\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.10\r\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.1\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nX-Long: a\r\n b\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nNo colon\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\nX: a\rb\r\n\r\n",
+            b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+            b"GET * HTTP/1.1\r\nHost: a\r\n\r\n",
+            b"GET example.com:443 HTTP/1.1\r\nHost: a\r\n\r\n",
+        ] {
+            assert_eq!(
+                error_status(parse_http_connection(bad, &RequestLimits::default())),
+                Some(400),
+                "{}",
+                String::from_utf8_lossy(bad)
+            );
+        }
+    }
+
+    #[test]
+    fn content_length_is_only_digits() {
+        let parse = |length: &str| {
+            parse_http_connection(
+                format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {length}\r\n\r\n")
+                    .as_bytes(),
+                &RequestLimits::default(),
+            )
+        };
+
+        for bad in ["+5", "-5", "5 5", "0x5", "", "5, +5", "5,"] {
+            assert_eq!(error_status(parse(bad)), Some(400), "{bad:?}");
+        }
+        assert_eq!(error_status(parse("99999999999999999999999999")), Some(413));
+
+        // Repeated values are compared as lengths, not as text
+        for same in ["5", "5, 05", "005,5"] {
+            assert_eq!(
+                body_framing(&parse(same).unwrap()).unwrap(),
+                BodyFraming::Length(5),
+                "{same:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_crlf_ends_a_line() {
+        for bad in [
+            &b"POST / HTTP/1.1\r\nHost: a\r\n\nTransfer-Encoding: chunked\r\n\r\n"[..],
+            b"GET / HTTP/1.1\nHost: a\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\n\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\rX: b\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n\r\nX: b\r\n\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\r\n",
+            b"GET / HTTP/1.1\r\nHost: a\n\n",
+        ] {
+            assert_eq!(
+                error_status(parse_http_connection(bad, &RequestLimits::default())),
+                Some(400),
+                "{:?}",
+                String::from_utf8_lossy(bad)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_every_target_form() {
+        let absolute = parse_http_connection(
+            b"GET http://example.com:8080?a=1 HTTP/1.1\r\nHost: other\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(absolute.target_form(), RequestTargetForm::Absolute);
+        assert_eq!(absolute.request_path, "/");
+        assert_eq!(absolute.query_value("a"), Some("1"));
+        assert_eq!(absolute.headers.get("Host"), Some("example.com:8080"));
+
+        let authority = parse_http_connection(
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(authority.target_form(), RequestTargetForm::Authority);
+        assert_eq!(authority.request_path, "example.com:443");
+
+        let asterisk = parse_http_connection(
+            b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(asterisk.target_form(), RequestTargetForm::Asterisk);
+    }
+
+    #[test]
+    fn parses_extension_methods() {
+        let patch = parse_http_connection(
+            b"PATCH /item HTTP/1.1\r\nHost: a\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(patch.http_request_type, HttpRequestType::PATCH);
+
+        let brew = parse_http_connection(
+            b"BREW /pot HTTP/1.1\r\nHost: a\r\n\r\n",
+            &RequestLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            brew.http_request_type,
+            HttpRequestType::Extension("BREW".to_string())
+        );
+        assert_eq!(brew.http_request_type.as_str(), "BREW");
+    }
+
+    #[derive(Debug)]
+    struct RefuseUploads;
+
+    impl HttpMiddleware for RefuseUploads {
+        fn accepts_body(&self, parse_return_data: &ParseReturnData) -> bool {
+            parse_return_data.request_path != "/upload"
+        }
+    }
+
+    #[test]
+    fn answers_expectations_before_the_body() {
+        let settings = HttpServerSettings {
+            middleware: vec![Arc::new(RefuseUploads)],
+            limits: limits(100),
+            ..Default::default()
+        };
+        let expect = |request: &[u8]| {
+            check_expectation(
+                &settings,
+                &parse_http_connection(request, &RequestLimits::default()).unwrap(),
+            )
+        };
+
+        assert!(expect(
+            b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 10\r\n\r\n"
+        )
+        .unwrap());
+        // A chunked body is under the limit until it turns out not to be while it is read
+        assert!(expect(
+            b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nTransfer-Encoding: chunked\r\n\r\n"
+        )
+        .unwrap());
+        assert!(!expect(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n").unwrap());
+        assert!(!expect(b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-Continue\r\n\r\n").unwrap());
+        assert!(
+            !expect(b"POST / HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 10\r\n\r\n")
+                .unwrap()
+        );
+
+        let status = |result: Result<bool, StdStupidError>| match result {
+            Err(StdStupidError::BadRequest(e)) => Some(e.status),
+            _ => None,
+        };
+        assert_eq!(
+            status(expect(b"POST / HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 101\r\n\r\n")),
+            Some(413)
+        );
+        assert_eq!(
+            status(expect(b"POST /upload HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Length: 10\r\n\r\n")),
+            Some(417)
+        );
+        assert_eq!(
+            status(expect(
+                b"POST / HTTP/1.1\r\nHost: a\r\nExpect: fast\r\n\r\n"
+            )),
+            Some(417)
+        );
+    }
+
+    #[test]
+    fn only_multipart_routes_take_uploads() {
+        let route = |path: &str, multipart| HttpRoute {
+            path: path.to_string(),
+            methods: vec![HttpRequestType::POST],
+            handler: Arc::new(|_| HttpResponseStruct::new()),
+            ip_filter: None,
+            multipart,
+        };
+        let mut settings = HttpServerSettings {
+            routes: vec![route("/upload", true), route("/form", false)],
+            limits: limits(100),
+            ..Default::default()
+        };
+        let upload = |path: &str, length: usize| {
+            parse_http_connection(
+                format!("POST {path} HTTP/1.1\r\nHost: a\r\nExpect: 100-continue\r\nContent-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {length}\r\n\r\n").as_bytes(),
+                &RequestLimits::default(),
+            )
+            .unwrap()
+        };
+        let status = |result: Result<(), StdStupidError>| match result {
+            Err(StdStupidError::BadRequest(e)) => Some(e.status),
+            _ => None,
+        };
+
+        assert_eq!(
+            route_multipart_boundary(&settings, &upload("/upload", 1000)).as_deref(),
+            Some("XyZ")
+        );
+        assert!(check_expectation(&settings, &upload("/upload", 1000)).unwrap());
+
+        // Anywhere else a multipart body is just a body, and has to fit the max body size
+        assert_eq!(
+            route_multipart_boundary(&settings, &upload("/form", 1000)),
+            None
+        );
+        assert_eq!(
+            route_multipart_boundary(&settings, &upload("/nowhere", 1000)),
+            None
+        );
+        assert_eq!(
+            status(check_expectation(&settings, &upload("/form", 1000)).map(|_| ())),
+            Some(413)
+        );
+
+        assert_eq!(status(check_body(&settings, &upload("/upload", 10))), None);
+        settings.routes[0].ip_filter = Some(IpFilter::default().allow("10.0.0.0/8").unwrap());
+        assert_eq!(
+            status(check_body(&settings, &upload("/upload", 10))),
+            Some(403)
+        );
+        // Nothing is continued for a client the route won't take the body from either
+        assert_eq!(
+            status(check_expectation(&settings, &upload("/upload", 10)).map(|_| ())),
+            Some(403)
+        );
+        assert_eq!(
+            status(check_expectation(&settings, &upload("/form", 10)).map(|_| ())),
+            None
+        );
+        settings.routes[0].ip_filter = None;
+        settings.middleware = vec![Arc::new(RefuseUploads)];
+        assert_eq!(
+            status(check_body(&settings, &upload("/upload", 10))),
+            Some(417)
+        );
+    }
+
+    #[test]
+    fn refuses_bodies_without_an_expectation() {
+        let settings = HttpServerSettings {
+            middleware: vec![Arc::new(RefuseUploads)],
+            ..Default::default()
+        };
+        let check = |request: &[u8]| match check_body(
+            &settings,
+            &parse_http_connection(request, &RequestLimits::default()).unwrap(),
+        ) {
+            Err(StdStupidError::BadRequest(e)) => Some(e.status),
+            _ => None,
+        };
+
+        assert_eq!(
+            check(b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n"),
+            Some(417)
+        );
+        assert_eq!(
+            check(b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Some(417)
+        );
+        // Without a body there is nothing to refuse
+        assert_eq!(check(b"GET /upload HTTP/1.1\r\nHost: a\r\n\r\n"), None);
+        assert_eq!(
+            check(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\n"),
+            None
+        );
     }
 }
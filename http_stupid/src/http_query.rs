@@ -0,0 +1,221 @@
+use std::{collections::HashMap, str};
+
+use errors_stupid::{BadRequestError, StdStupidError};
+
+/// Decodes the `%xx` escapes in the text, and when `plus_as_space` is set also turns `+` into a
+/// space like `application/x-www-form-urlencoded` does. Escapes which are cut off or aren't two
+/// hex digits, and decoded bytes which aren't valid UTF-8, are rejected as a bad request instead
+/// of being passed through as is.
+pub fn percent_decode(encoded: &str, plus_as_space: bool) -> Result<String, StdStupidError> {
+    let bytes = encoded.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let escape = bytes
+                    .get(i + 1..i + 3)
+                    .ok_or_else(|| BadRequestError::new("Percent escape is cut off"))?;
+
+                match (hex_value(escape[0]), hex_value(escape[1])) {
+                    (Some(high), Some(low)) => decoded.push(high << 4 | low),
+                    _ => return Err(BadRequestError::new("Percent escape is not valid hex").into()),
+                }
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded)
+        .map_err(|_| BadRequestError::new("Percent decoded text is not valid UTF-8").into())
+}
+
+fn hex_value(character: u8) -> Option<u8> {
+    match character {
+        b'0'..=b'9' => Some(character - b'0'),
+        b'a'..=b'f' => Some(character - b'a' + 10),
+        b'A'..=b'F' => Some(character - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parses a query string such as `a=1&b=two+words&a=2` into a map of each decoded key to all of its
+/// decoded values in the order they were given. A key without `=` gets an empty value and empty
+/// pairs (e.g. from `&&`) are skipped.
+pub fn parse_query(query: &str) -> Result<HashMap<String, Vec<String>>, StdStupidError> {
+    let mut parsed: HashMap<String, Vec<String>> = HashMap::new();
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+        parsed
+            .entry(percent_decode(key, true)?)
+            .or_default()
+            .push(percent_decode(value, true)?);
+    }
+
+    Ok(parsed)
+}
+
+/// Limits on an `application/x-www-form-urlencoded` body, so a form can't be used to make us
+/// allocate far more than any real form needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormLimits {
+    pub max_size: usize,
+    pub max_fields: usize,
+}
+
+impl Default for FormLimits {
+    fn default() -> Self {
+        FormLimits {
+            max_size: 64 * 1024,
+            max_fields: 1000,
+        }
+    }
+}
+
+/// Parses an `application/x-www-form-urlencoded` body the same way as a query string, after
+/// checking it is within the limits and is valid UTF-8.
+pub fn parse_form(
+    body: &[u8],
+    form_limits: &FormLimits,
+) -> Result<HashMap<String, Vec<String>>, StdStupidError> {
+    if body.len() > form_limits.max_size {
+        return Err(BadRequestError::new("Form body is larger than allowed").into());
+    }
+
+    let body =
+        str::from_utf8(body).map_err(|_| BadRequestError::new("Form body is not valid UTF-8"))?;
+
+    if body.split('&').filter(|pair| !pair.is_empty()).count() > form_limits.max_fields {
+        return Err(BadRequestError::new("Form has more fields than allowed").into());
+    }
+
+    parse_query(body)
+}
+
+/// Splits a request target into its decoded and normalized (see [`normalize_path()`]) path and
+/// parsed query, any `#fragment` a client sent by mistake is dropped.
+pub fn split_request_target(
+    request_target: &str,
+) -> Result<(String, HashMap<String, Vec<String>>), StdStupidError> {
+    let request_target = request_target
+        .split_once('#')
+        .map_or(request_target, |(before, _)| before);
+
+    let (path, query) = request_target
+        .split_once('?')
+        .unwrap_or((request_target, ""));
+
+    Ok((
+        normalize_path(&percent_decode(path, false)?),
+        parse_query(query)?,
+    ))
+}
+
+/// Collapses repeated slashes and removes `.` segments from a path, `..` removes the segment
+/// before it but never goes above the root. Paths are matched and served in this form, so
+/// `//admin/./secret` can't be used to get around a check on `/admin`.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    let trailing_slash = path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..");
+    if trailing_slash && !segments.is_empty() {
+        normalized.push('/');
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod http_query_tests {
+    use crate::http_query::*;
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize_path("//admin/secret.html"), "/admin/secret.html");
+        assert_eq!(normalize_path("/./admin//./users/"), "/admin/users/");
+        assert_eq!(normalize_path("/a/../../b/.."), "/");
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path(""), "/");
+
+        let (path, _) = split_request_target("//admin%2F.%2Fsecret.html").unwrap();
+        assert_eq!(path, "/admin/secret.html");
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(
+            percent_decode("/a%20b/%C3%A9+c", false).unwrap(),
+            "/a b/é+c"
+        );
+        assert_eq!(percent_decode("a+b%2Bc", true).unwrap(), "a b+c");
+    }
+
+    #[test]
+    fn rejects_malformed_escapes() {
+        for malformed in ["%", "%2", "abc%zz", "%G0", "%ff%fe"] {
+            assert!(matches!(
+                percent_decode(malformed, false),
+                Err(StdStupidError::BadRequest(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_multi_valued_query() {
+        let (path, query) =
+            split_request_target("/search%20page?q=a+b&tag=x&tag=y&flag&&").unwrap();
+
+        assert_eq!(path, "/search page");
+        assert_eq!(query["q"], vec!["a b"]);
+        assert_eq!(query["tag"], vec!["x", "y"]);
+        assert_eq!(query["flag"], vec![""]);
+        assert_eq!(query.len(), 3);
+    }
+
+    #[test]
+    fn parses_form_within_limits() {
+        let form =
+            parse_form(b"name=J%C3%B6rg+M&lang=rust&lang=c", &FormLimits::default()).unwrap();
+
+        assert_eq!(form["name"], vec!["Jörg M"]);
+        assert_eq!(form["lang"], vec!["rust", "c"]);
+
+        let limits = FormLimits {
+            max_size: 16,
+            max_fields: 2,
+        };
+        assert!(parse_form(b"a=1&b=2&c=3", &limits).is_err());
+        assert!(parse_form(b"a=0123456789abcdef", &limits).is_err());
+        assert!(parse_form(b"a=\xff", &limits).is_err());
+    }
+
+    #[test]
+    fn target_without_query() {
+        let (path, query) = split_request_target("/index.html#top").unwrap();
+
+        assert_eq!(path, "/index.html");
+        assert!(query.is_empty());
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use errors_stupid::TooManyRequestsError;
+
+use crate::{http_ip_filter::IpNetwork, http_struct::ParseReturnData};
+
+/// The most buckets which are tracked at once, once there are this many the bucket at the front
+/// of the order is dropped to make room for a new client.
+const MAX_BUCKETS: usize = 65536;
+
+/// How many of the oldest buckets are looked at whenever a bucket is added, the ones which have
+/// filled up again are dropped as they are no different from a client which was never seen.
+const PRUNE_PER_INSERT: usize = 2;
+
+/// The longest `Retry-After` handed out, rates so low the wait would be longer (or a rate of 0)
+/// get this instead.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Function which picks what a request is rate limited by, such as an API key or the logged in
+/// user. Returning None falls back to the address of the client.
+pub type RateLimitKey = Arc<dyn Fn(&ParseReturnData) -> Option<String> + Send + Sync>;
+
+/// Settings for limiting how many requests a client may send, every client gets a bucket of
+/// `burst` requests which refills at `requests_per_second`, requests which find it empty are
+/// answered with a 429 and a `Retry-After` of when the next request would be allowed.
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    pub requests_per_second: f64,
+    pub burst: u32,
+    pub key: Option<RateLimitKey>,
+}
+
+impl fmt::Debug for RateLimitConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RateLimitConfig")
+            .field("requests_per_second", &self.requests_per_second)
+            .field("burst", &self.burst)
+            .field("key", &self.key.as_ref().map(|_| "Fn"))
+            .finish()
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 20,
+            key: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Adds the tokens which came in since the bucket was last updated.
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+/// The buckets of every client, along with the order they were added in so old buckets can be
+/// found without going through all of them.
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, TokenBucket>,
+    order: VecDeque<String>,
+}
+
+impl Buckets {
+    /// Makes room for a new bucket, buckets at the front of the order which have filled up again
+    /// are dropped and the others are moved to the back, and if there is still no room the oldest
+    /// bucket is dropped.
+    fn make_room(&mut self, now: Instant, rate: f64, burst: f64) {
+        for _ in 0..PRUNE_PER_INSERT.min(self.order.len()) {
+            let Some(key) = self.order.pop_front() else {
+                break;
+            };
+
+            if let Some(bucket) = self.buckets.get_mut(&key) {
+                bucket.refill(now, rate, burst);
+                if bucket.tokens < burst {
+                    self.order.push_back(key);
+                } else {
+                    self.buckets.remove(&key);
+                }
+            }
+        }
+
+        while self.buckets.len() >= MAX_BUCKETS {
+            match self.order.pop_front() {
+                Some(key) => self.buckets.remove(&key),
+                None => break,
+            };
+        }
+    }
+}
+
+/// Token bucket rate limiter shared by every connection of the server, clones share their
+/// buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Arc::new(Mutex::new(Buckets::default())),
+        }
+    }
+
+    /// Takes a token from the bucket of the client which sent the request, or fails with how
+    /// long it has to wait if there is none.
+    pub fn check(&self, parse_return_data: &ParseReturnData) -> Result<(), TooManyRequestsError> {
+        let key = self
+            .config
+            .key
+            .as_ref()
+            .and_then(|key| key(parse_return_data))
+            .or_else(|| parse_return_data.client_address.map(address_key))
+            .unwrap_or_default();
+
+        self.take(&key, Instant::now())
+    }
+
+    fn take(&self, key: &str, now: Instant) -> Result<(), TooManyRequestsError> {
+        let burst = f64::from(self.config.burst.max(1));
+        let rate = self.config.requests_per_second;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if !buckets.buckets.contains_key(key) {
+            buckets.make_room(now, rate, burst);
+            buckets.order.push_back(key.to_string());
+        }
+
+        let bucket = buckets
+            .buckets
+            .entry(key.to_string())
+            .or_insert(TokenBucket {
+                tokens: burst,
+                updated: now,
+            });
+        bucket.refill(now, rate, burst);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        // A tiny rate would make the wait too long for a Duration, and a rate of 0 never refills
+        let wait = Duration::try_from_secs_f64((1.0 - bucket.tokens) / rate)
+            .unwrap_or(MAX_RETRY_AFTER)
+            .min(MAX_RETRY_AFTER);
+        Err(TooManyRequestsError::new(
+            format!("Rate limit reached for {key}"),
+            wait.as_secs_f64().ceil() as u64,
+        ))
+    }
+}
+
+/// The key of the bucket of a client address, every address of an IPv6 /64 shares a bucket as
+/// that is usually handed out to a single client, which could otherwise pick a new address for
+/// every request.
+fn address_key(ip_address: IpAddr) -> String {
+    match ip_address.to_canonical() {
+        IpAddr::V4(v4) => v4.to_string(),
+        v6 => IpNetwork::new(v6, 64)
+            .map(|network| network.to_string())
+            .unwrap_or_else(|_| v6.to_string()),
+    }
+}
+
+/// Counts the open connections of every client address, so a single client can't take up every
+/// worker of the server. Addresses are grouped the same way as for rate limiting, so an IPv6
+/// client can't get around the cap by connecting from every address of its /64.
+#[derive(Debug, Default)]
+pub struct ConnectionTracker {
+    connections: Mutex<HashMap<String, usize>>,
+}
+
+impl ConnectionTracker {
+    /// Counts a new connection from the address, returning a guard which counts it as closed
+    /// again when dropped, or None if the address already has `max_connections` open.
+    pub fn acquire(
+        self: &Arc<Self>,
+        ip_address: IpAddr,
+        max_connections: usize,
+    ) -> Option<ConnectionGuard> {
+        let key = address_key(ip_address);
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        let count = connections.entry(key.clone()).or_insert(0);
+
+        if *count >= max_connections {
+            if *count == 0 {
+                connections.remove(&key);
+            }
+            return None;
+        }
+
+        *count += 1;
+        Some(ConnectionGuard {
+            tracker: Arc::clone(self),
+            key,
+        })
+    }
+
+    /// How many connections the address (or the rest of its IPv6 /64) has open.
+    pub fn open_connections(&self, ip_address: IpAddr) -> usize {
+        let connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        connections
+            .get(&address_key(ip_address))
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+/// An open connection counted by a [`ConnectionTracker`].
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    key: String,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self
+            .tracker
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(count) = connections.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod http_rate_limit_tests {
+    use crate::http_rate_limit::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 2.0,
+            burst: 2,
+            key: None,
+        });
+        let start = Instant::now();
+
+        assert!(limiter.take("a", start).is_ok());
+        assert!(limiter.take("a", start).is_ok());
+        assert_eq!(limiter.take("a", start).unwrap_err().retry_after, 1);
+        // Other clients have their own bucket
+        assert!(limiter.take("b", start).is_ok());
+
+        assert!(limiter
+            .take("a", start + Duration::from_millis(500))
+            .is_ok());
+        assert!(limiter
+            .take("a", start + Duration::from_millis(600))
+            .is_err());
+        assert!(limiter.take("a", start + Duration::from_secs(10)).is_ok());
+        assert!(limiter.take("a", start + Duration::from_secs(10)).is_ok());
+        assert!(limiter.take("a", start + Duration::from_secs(10)).is_err());
+    }
+
+    #[test]
+    fn buckets_stay_bounded() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 1.0,
+            burst: 1,
+            key: None,
+        });
+        let start = Instant::now();
+
+        for n in 0..MAX_BUCKETS + 10 {
+            assert!(limiter.take(&n.to_string(), start).is_ok());
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), MAX_BUCKETS);
+        assert_eq!(buckets.order.len(), MAX_BUCKETS);
+        assert!(buckets.buckets.contains_key(&(MAX_BUCKETS + 9).to_string()));
+        drop(buckets);
+
+        // Once they have refilled, buckets are dropped a few at a time as new clients come in
+        let later = start + Duration::from_secs(5);
+        for n in 0..10 {
+            assert!(limiter.take(&format!("later {n}"), later).is_ok());
+        }
+        assert_eq!(
+            limiter.buckets.lock().unwrap().buckets.len(),
+            MAX_BUCKETS - 10
+        );
+    }
+
+    #[test]
+    fn ipv6_clients_share_a_bucket_per_64() {
+        assert_eq!(
+            address_key("2001:db8:1:2:aaaa::1".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(
+            address_key("2001:db8:1:2:bbbb::2".parse().unwrap()),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(address_key("::ffff:10.0.0.1".parse().unwrap()), "10.0.0.1");
+        assert_eq!(address_key("10.0.0.1".parse().unwrap()), "10.0.0.1");
+    }
+
+    #[test]
+    fn tiny_rates_wait_the_longest() {
+        for requests_per_second in [1e-20, 0.0, -1.0] {
+            let limiter = RateLimiter::new(RateLimitConfig {
+                requests_per_second,
+                burst: 1,
+                key: None,
+            });
+            let now = Instant::now();
+
+            assert!(limiter.take("a", now).is_ok());
+            assert_eq!(
+                limiter.take("a", now).unwrap_err().retry_after,
+                MAX_RETRY_AFTER.as_secs()
+            );
+        }
+    }
+
+    #[test]
+    fn caps_connections_per_address() {
+        let tracker = Arc::new(ConnectionTracker::default());
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let other: IpAddr = "::1".parse().unwrap();
+
+        let first = tracker.acquire(local, 2).unwrap();
+        let second = tracker.acquire(local, 2).unwrap();
+        assert!(tracker.acquire(local, 2).is_none());
+        assert!(tracker.acquire(other, 2).is_some());
+        assert_eq!(tracker.open_connections(local), 2);
+
+        drop(first);
+        assert!(tracker.acquire(local, 2).is_some());
+        drop(second);
+        assert_eq!(tracker.open_connections(local), 0);
+        assert!(tracker.acquire(local, 0).is_none());
+        assert!(tracker.connections.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn caps_connections_per_ipv6_network() {
+        let tracker = Arc::new(ConnectionTracker::default());
+        let first: IpAddr = "2001:db8::1".parse().unwrap();
+        let same_network: IpAddr = "2001:db8::ffff:1".parse().unwrap();
+        let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+
+        let _first = tracker.acquire(first, 1).unwrap();
+        assert!(tracker.acquire(same_network, 1).is_none());
+        assert_eq!(tracker.open_connections(same_network), 1);
+        assert!(tracker
+            .acquire("2001:db8:0:1::1".parse().unwrap(), 1)
+            .is_some());
+
+        let _mapped = tracker.acquire(mapped, 1).unwrap();
+        assert!(tracker.acquire("10.0.0.1".parse().unwrap(), 1).is_none());
+    }
+}
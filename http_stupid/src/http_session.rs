@@ -0,0 +1,576 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::PathBuf,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use errors_stupid::StdStupidError;
+use standard_stupid::{
+    hashing::{hmac_sha256, to_hex_string},
+    json::JsonValue,
+    random_bytes,
+    thread_manager::ThreadPool,
+};
+use tracing::{debug, error};
+
+use crate::{
+    http_auth::constant_time_eq,
+    http_cookie::{SameSite, SetCookie},
+    http_struct::{HttpResponseStruct, ParseReturnData},
+};
+
+/// What gets stored for a session, the values set on it and when it stops being valid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionData {
+    pub values: HashMap<String, String>,
+    pub expires: SystemTime,
+}
+
+/// Somewhere sessions can be kept between requests. Session IDs are always 64 lowercase hex
+/// characters by the time they reach the store.
+pub trait SessionStore: fmt::Debug + Send + Sync {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, StdStupidError>;
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), StdStupidError>;
+    fn remove(&self, id: &str) -> Result<(), StdStupidError>;
+    /// Removes every session which expired before `now`.
+    fn cleanup(&self, now: SystemTime) -> Result<(), StdStupidError>;
+}
+
+/// Keeps sessions in memory, they are lost when the server stops.
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, SessionData>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, StdStupidError> {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), StdStupidError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), data.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), StdStupidError> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn cleanup(&self, now: SystemTime) -> Result<(), StdStupidError> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, data| data.expires > now);
+        Ok(())
+    }
+}
+
+/// Keeps every session as a JSON file named after its ID inside of a directory, so sessions
+/// survive the server restarting.
+#[derive(Debug)]
+pub struct FileSessionStore {
+    directory: PathBuf,
+}
+
+impl FileSessionStore {
+    /// Creates the store, creating the directory if it doesn't exist yet.
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<Self, StdStupidError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(FileSessionStore { directory })
+    }
+
+    fn read(&self, path: &PathBuf) -> Result<SessionData, StdStupidError> {
+        let json = JsonValue::parse(fs::read_to_string(path)?)?;
+
+        let expires = json
+            .get("expires")
+            .and_then(|e| e.as_f64())
+            .map(|e| UNIX_EPOCH + Duration::from_secs(e as u64))
+            .unwrap_or(UNIX_EPOCH);
+        let values = match json.get("values") {
+            Some(JsonValue::Object(members)) => members
+                .iter()
+                .filter_map(|(name, value)| Some((name.clone(), value.as_str()?.to_string())))
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        Ok(SessionData { values, expires })
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&self, id: &str) -> Result<Option<SessionData>, StdStupidError> {
+        let path = self.directory.join(id);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        self.read(&path).map(Some)
+    }
+
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), StdStupidError> {
+        let expires = data
+            .expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let json = JsonValue::Object(vec![
+            ("expires".to_string(), JsonValue::Number(expires as f64)),
+            (
+                "values".to_string(),
+                JsonValue::Object(
+                    data.values
+                        .iter()
+                        .map(|(name, value)| (name.clone(), JsonValue::from(value.as_str())))
+                        .collect(),
+                ),
+            ),
+        ]);
+
+        // Written next to it first so a reader never sees half a file, under a name of its own so
+        // two saves of the same session can't write into the same file. Sessions say who is
+        // logged in, so nobody else gets to read them
+        let temporary = self
+            .directory
+            .join(format!("{id}.{}.tmp", to_hex_string(&random_bytes(8)?)));
+        let written = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&temporary)
+            .and_then(|mut file| file.write_all(json.to_string().as_bytes()))
+            .and_then(|_| fs::rename(&temporary, self.directory.join(id)));
+
+        if written.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+
+        Ok(written?)
+    }
+
+    fn remove(&self, id: &str) -> Result<(), StdStupidError> {
+        match fs::remove_file(self.directory.join(id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn cleanup(&self, now: SystemTime) -> Result<(), StdStupidError> {
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let is_session = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(is_session_id);
+
+            if !is_session {
+                continue;
+            }
+
+            // Files which can't be read anymore are of no use either
+            let expired = self.read(&path).map_or(true, |data| data.expires <= now);
+            if expired {
+                let _ = fs::remove_file(&path);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Settings for a [`SessionManager`], sessions which aren't used for `lifetime` expire and
+/// expired sessions are removed from the store every `cleanup_interval`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionConfig {
+    pub cookie_name: String,
+    pub cookie_path: String,
+    pub lifetime: Duration,
+    pub cleanup_interval: Duration,
+    pub secure: bool,
+    pub same_site: SameSite,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            cookie_name: "session".to_string(),
+            cookie_path: "/".to_string(),
+            lifetime: Duration::from_secs(24 * 60 * 60),
+            cleanup_interval: Duration::from_secs(10 * 60),
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+}
+
+/// The session of a single client, changes are only kept once it is passed to
+/// [`SessionManager::save()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    id: String,
+    previous_id: Option<String>,
+    values: HashMap<String, String>,
+    is_new: bool,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// If the client didn't have a valid session yet.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(|value| value.as_str())
+    }
+
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.values.insert(key.into(), value.into());
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.values.remove(key)
+    }
+
+    /// Gives the session a new ID while keeping its values, which should be done whenever a
+    /// user logs in so an ID someone else planted on the client becomes useless.
+    pub fn regenerate_id(&mut self) -> Result<(), StdStupidError> {
+        let previous = std::mem::replace(&mut self.id, new_session_id()?);
+        self.previous_id.get_or_insert(previous);
+
+        Ok(())
+    }
+}
+
+/// Loads and saves sessions for requests, with the session ID kept in a cookie signed with
+/// HMAC-SHA256 so IDs which weren't handed out by us are rejected before they reach the store.
+///
+/// Handlers get the manager by capturing it in an `Arc`, load the session of the request with
+/// [`SessionManager::load()`] and save it onto the response with [`SessionManager::save()`].
+/// Expired sessions are cleaned up on a background thread every `cleanup_interval`.
+pub struct SessionManager {
+    store: Arc<dyn SessionStore>,
+    secret: Vec<u8>,
+    config: SessionConfig,
+    stop_cleanup: Option<mpsc::Sender<()>>,
+    // Dropped after `stop_cleanup`, which ends the cleanup job so the pool can join its thread
+    _cleanup_pool: ThreadPool,
+}
+
+impl fmt::Debug for SessionManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SessionManager")
+            .field("store", &self.store)
+            .field("config", &self.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionManager {
+    /// Creates the manager, `secret` is the key the session cookies are signed with and should be
+    /// at least 32 random bytes which stay the same across restarts (if the store does).
+    pub fn new<S: SessionStore + 'static>(store: S, secret: &[u8], config: SessionConfig) -> Self {
+        let store: Arc<dyn SessionStore> = Arc::new(store);
+        let (stop_cleanup, stop_receiver) = mpsc::channel::<()>();
+        let cleanup_pool = ThreadPool::new(1);
+
+        let cleanup_store = Arc::clone(&store);
+        let cleanup_interval = config.cleanup_interval;
+        cleanup_pool.execute(move || {
+            // Waking up because of the timeout means it is time to clean up, anything else means
+            // the manager was dropped
+            while let Err(mpsc::RecvTimeoutError::Timeout) =
+                stop_receiver.recv_timeout(cleanup_interval)
+            {
+                debug!("Cleaning up expired sessions");
+                if let Err(e) = cleanup_store.cleanup(SystemTime::now()) {
+                    error!("Failed to clean up sessions: {:?}", e);
+                }
+            }
+        });
+
+        SessionManager {
+            store,
+            secret: secret.to_vec(),
+            config,
+            stop_cleanup: Some(stop_cleanup),
+            _cleanup_pool: cleanup_pool,
+        }
+    }
+
+    /// Loads the session of the request, or starts a new one when the request has no session
+    /// cookie or one which is forged, unknown or expired. Fails only when no random bytes could
+    /// be read for the ID of a new session.
+    pub fn load(&self, parse_return_data: &ParseReturnData) -> Result<Session, StdStupidError> {
+        let existing = parse_return_data
+            .cookies()
+            .get(&self.config.cookie_name)
+            .and_then(|cookie| self.verify(cookie))
+            .and_then(|id| match self.store.load(&id) {
+                Ok(Some(data)) if data.expires > SystemTime::now() => Some((id, data)),
+                Ok(_) => None,
+                Err(e) => {
+                    error!("Failed to load session: {:?}", e);
+                    None
+                }
+            });
+
+        Ok(match existing {
+            Some((id, data)) => Session {
+                id,
+                previous_id: None,
+                values: data.values,
+                is_new: false,
+            },
+            None => Session {
+                id: new_session_id()?,
+                previous_id: None,
+                values: HashMap::new(),
+                is_new: true,
+            },
+        })
+    }
+
+    /// Stores the session, extending its lifetime, and sets the session cookie on the response.
+    pub fn save(
+        &self,
+        session: &Session,
+        response: &mut HttpResponseStruct,
+    ) -> Result<(), StdStupidError> {
+        if let Some(previous_id) = &session.previous_id {
+            self.store.remove(previous_id)?;
+        }
+
+        self.store.save(
+            &session.id,
+            &SessionData {
+                values: session.values.clone(),
+                expires: SystemTime::now() + self.config.lifetime,
+            },
+        )?;
+
+        let cookie = SetCookie::new(&self.config.cookie_name, &self.sign(&session.id))?
+            .path(&self.config.cookie_path)?
+            .max_age(self.config.lifetime)
+            .http_only(true)
+            .secure(self.config.secure)
+            .same_site(self.config.same_site);
+        response.add_cookie(&cookie);
+
+        Ok(())
+    }
+
+    /// Removes the session from the store and tells the client to forget the cookie, e.g. when
+    /// a user logs out.
+    pub fn destroy(
+        &self,
+        session: &Session,
+        response: &mut HttpResponseStruct,
+    ) -> Result<(), StdStupidError> {
+        self.store.remove(&session.id)?;
+        if let Some(previous_id) = &session.previous_id {
+            self.store.remove(previous_id)?;
+        }
+
+        let cookie =
+            SetCookie::removal(&self.config.cookie_name)?.path(&self.config.cookie_path)?;
+        response.add_cookie(&cookie);
+
+        Ok(())
+    }
+
+    /// Removes expired sessions from the store right away instead of waiting for the next cleanup.
+    pub fn cleanup(&self) -> Result<(), StdStupidError> {
+        self.store.cleanup(SystemTime::now())
+    }
+
+    fn sign(&self, id: &str) -> String {
+        format!(
+            "{id}.{}",
+            to_hex_string(&hmac_sha256(&self.secret, id.as_bytes()))
+        )
+    }
+
+    /// Checks the signature of a session cookie, returning the session ID if it is ours.
+    fn verify(&self, cookie: &str) -> Option<String> {
+        let (id, _) = cookie.split_once('.')?;
+
+        (is_session_id(id) && constant_time_eq(self.sign(id).as_bytes(), cookie.as_bytes()))
+            .then(|| id.to_string())
+    }
+}
+
+impl Drop for SessionManager {
+    fn drop(&mut self) {
+        drop(self.stop_cleanup.take());
+    }
+}
+
+fn new_session_id() -> Result<String, StdStupidError> {
+    Ok(to_hex_string(&random_bytes(32)?))
+}
+
+fn is_session_id(id: &str) -> bool {
+    id.len() == 64
+        && id
+            .bytes()
+            .all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c))
+}
+
+#[cfg(test)]
+mod http_session_tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use crate::http_headers::HeaderMap;
+    use crate::http_session::*;
+
+    const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn request(cookie: Option<&str>) -> ParseReturnData {
+        let mut headers = HeaderMap::new();
+        if let Some(cookie) = cookie {
+            headers.insert("Cookie".to_string(), cookie.to_string());
+        }
+
+        ParseReturnData {
+            headers,
+            ..Default::default()
+        }
+    }
+
+    /// Saves the session and returns the `Cookie` header a browser would send back
+    fn save(manager: &SessionManager, session: &Session) -> String {
+        let mut response = HttpResponseStruct::new();
+        manager.save(session, &mut response).unwrap();
+
+        let set_cookie = response.get_header("Set-Cookie").unwrap();
+        set_cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn session_survives_round_trip() {
+        let manager = SessionManager::new(MemorySessionStore::new(), SECRET, Default::default());
+
+        let mut session = manager.load(&request(None)).unwrap();
+        assert!(session.is_new());
+        session.insert("user", "alice");
+        let cookie = save(&manager, &session);
+
+        let loaded = manager.load(&request(Some(&cookie))).unwrap();
+        assert!(!loaded.is_new());
+        assert_eq!(loaded.id(), session.id());
+        assert_eq!(loaded.get("user"), Some("alice"));
+    }
+
+    #[test]
+    fn forged_cookies_start_new_session() {
+        let manager = SessionManager::new(MemorySessionStore::new(), SECRET, Default::default());
+        let session = manager.load(&request(None)).unwrap();
+        let cookie = save(&manager, &session);
+
+        let other_secret = SessionManager::new(
+            MemorySessionStore::new(),
+            b"another secret",
+            Default::default(),
+        );
+        assert!(other_secret.load(&request(Some(&cookie))).unwrap().is_new());
+
+        let tampered = format!("session={}.{}", "0".repeat(64), &cookie[74..]);
+        assert!(manager.load(&request(Some(&tampered))).unwrap().is_new());
+        assert!(manager
+            .load(&request(Some("session=garbage")))
+            .unwrap()
+            .is_new());
+    }
+
+    #[test]
+    fn regenerating_id_drops_old_session() {
+        let manager = SessionManager::new(MemorySessionStore::new(), SECRET, Default::default());
+        let session = manager.load(&request(None)).unwrap();
+        let old_cookie = save(&manager, &session);
+
+        let mut session = manager.load(&request(Some(&old_cookie))).unwrap();
+        session.regenerate_id().unwrap();
+        let new_cookie = save(&manager, &session);
+
+        assert!(manager.load(&request(Some(&old_cookie))).unwrap().is_new());
+        assert!(!manager.load(&request(Some(&new_cookie))).unwrap().is_new());
+    }
+
+    #[test]
+    fn expired_sessions_are_cleaned_up() {
+        let directory = std::env::temp_dir().join(format!(
+            "std-stupid-sessions-{}",
+            to_hex_string(&random_bytes(8).unwrap())
+        ));
+        let config = SessionConfig {
+            lifetime: Duration::ZERO,
+            cleanup_interval: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let manager =
+            SessionManager::new(FileSessionStore::new(&directory).unwrap(), SECRET, config);
+
+        let mut session = manager.load(&request(None)).unwrap();
+        session.insert("user", "bob");
+        let cookie = save(&manager, &session);
+        assert!(directory.join(session.id()).exists());
+
+        assert!(manager.load(&request(Some(&cookie))).unwrap().is_new());
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!directory.join(session.id()).exists());
+
+        drop(manager);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn file_store_round_trips() {
+        let directory = std::env::temp_dir().join(format!(
+            "std-stupid-sessions-{}",
+            to_hex_string(&random_bytes(8).unwrap())
+        ));
+        let store = FileSessionStore::new(&directory).unwrap();
+        let data = SessionData {
+            values: HashMap::from([("quote".to_string(), "\"hi\"\n".to_string())]),
+            expires: UNIX_EPOCH + Duration::from_secs(4_000_000_000),
+        };
+        let id = new_session_id().unwrap();
+
+        store.save(&id, &data).unwrap();
+        assert_eq!(store.load(&id).unwrap(), Some(data));
+        let mode = fs::metadata(directory.join(&id))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+
+        store.remove(&id).unwrap();
+        assert_eq!(store.load(&id).unwrap(), None);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,23 +1,140 @@
-use std::collections::HashMap;
-
-use errors_stupid::{HttpServerError, StdStupidError};
+use std::{collections::HashMap, fmt, net::IpAddr, str, sync::Arc};
+
+use errors_stupid::{BadRequestError, HeaderError, HttpServerError, StdStupidError};
+use standard_stupid::json::JsonValue;
+
+use crate::{
+    http_compression::CompressionConfig,
+    http_cookie::{CookieJar, SetCookie},
+    http_cors::CorsConfig,
+    http_error_page::ErrorPage,
+    http_headers::{is_field_value, is_token, HeaderMap},
+    http_ip_filter::{IpFilter, IpNetwork},
+    http_limits::{LimitCounters, RequestLimits},
+    http_middleware::HttpMiddleware,
+    http_multipart::{MultipartLimits, MultipartPart},
+    http_query::{parse_form, FormLimits},
+    http_rate_limit::{ConnectionTracker, RateLimiter},
+};
 
 #[derive(Debug)]
 pub enum ConnectionReturn {
     TcpStream,
     SocketAddr,
 }
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum HttpRequestType {
     GET,
     POST,
     OPTIONS,
     HEAD,
     PUT,
+    PATCH,
     DELETE,
     TRACE,
     CONNECT,
-    INVALID,
+    /// Any other method, which can still be answered by a route added with it.
+    Extension(String),
+}
+
+impl HttpRequestType {
+    /// The method as it is written on the request line and inside of an `Allow` header.
+    pub fn as_str(&self) -> &str {
+        match self {
+            HttpRequestType::GET => "GET",
+            HttpRequestType::POST => "POST",
+            HttpRequestType::OPTIONS => "OPTIONS",
+            HttpRequestType::HEAD => "HEAD",
+            HttpRequestType::PUT => "PUT",
+            HttpRequestType::DELETE => "DELETE",
+            HttpRequestType::TRACE => "TRACE",
+            HttpRequestType::PATCH => "PATCH",
+            HttpRequestType::CONNECT => "CONNECT",
+            HttpRequestType::Extension(method) => method,
+        }
+    }
+}
+
+/// The version of HTTP a request was sent with, only HTTP/1.0 and HTTP/1.1 are served, the
+/// others are known so they can be refused with a 505.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HttpVersion {
+    Http09,
+    Http10,
+    Http11,
+    Http2,
+    Http3,
+    /// Any other `HTTP/major.minor`.
+    Unknown(u8, u8),
+}
+
+impl HttpVersion {
+    /// Parses the version as it is written on the request line, e.g. `HTTP/1.1`. Only a single
+    /// digit is allowed for the major and minor version so `HTTP/1.10` is not a version.
+    pub fn parse(version: &str) -> Option<Self> {
+        match version.strip_prefix("HTTP/")?.as_bytes() {
+            &[major, b'.', minor] if major.is_ascii_digit() && minor.is_ascii_digit() => {
+                Some(match (major - b'0', minor - b'0') {
+                    (0, 9) => HttpVersion::Http09,
+                    (1, 0) => HttpVersion::Http10,
+                    (1, 1) => HttpVersion::Http11,
+                    (2, 0) => HttpVersion::Http2,
+                    (3, 0) => HttpVersion::Http3,
+                    (major, minor) => HttpVersion::Unknown(major, minor),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        matches!(self, HttpVersion::Http10 | HttpVersion::Http11)
+    }
+
+    /// HTTP/1.1 keeps the connection open unless told to close it, HTTP/1.0 only does when the
+    /// client sends `Connection: keep-alive`.
+    pub fn keeps_alive_by_default(&self) -> bool {
+        *self == HttpVersion::Http11
+    }
+}
+
+impl fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpVersion::Http09 => f.write_str("HTTP/0.9"),
+            HttpVersion::Http10 => f.write_str("HTTP/1.0"),
+            HttpVersion::Http11 => f.write_str("HTTP/1.1"),
+            HttpVersion::Http2 => f.write_str("HTTP/2"),
+            HttpVersion::Http3 => f.write_str("HTTP/3"),
+            HttpVersion::Unknown(major, minor) => write!(f, "HTTP/{major}.{minor}"),
+        }
+    }
+}
+
+/// The form a request target is written in (RFC 9112 3.2), see
+/// [`ParseReturnData::target_form()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RequestTargetForm {
+    /// `/path?query`, used by almost every request.
+    Origin,
+    /// `http://example.com/path?query`, sent to proxies.
+    Absolute,
+    /// `example.com:443`, only used with CONNECT.
+    Authority,
+    /// `*`, only used with OPTIONS to ask about the server as a whole.
+    Asterisk,
+}
+
+/// How the end of a request body is found (RFC 9112 6.3), see
+/// [`crate::http_parser::body_framing()`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BodyFraming {
+    /// Neither `Content-Length` nor `Transfer-Encoding` was sent, so there is no body.
+    None,
+    /// The body is exactly this many bytes.
+    Length(usize),
+    /// The body is sent in chunks, ending with a chunk of size 0 and optional trailer fields.
+    Chunked,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -28,19 +145,215 @@ pub enum ServerFunction {
     Proxy,
 }
 
+/// Function called to respond to a request made to a route added with
+/// [`crate::HttpServer::add_route()`].
+pub type RouteHandler = Arc<dyn Fn(&ParseReturnData) -> HttpResponseStruct + Send + Sync>;
+
+/// A path which is answered by its handler instead of the server function, for the methods given.
+/// HEAD is allowed whenever GET is, and OPTIONS is always answered automatically. Clients which
+/// the `ip_filter` of the route doesn't allow get a 403.
+///
+/// Only routes with `multipart` set have `multipart/form-data` bodies parsed into parts, on any
+/// other path such a body is read into `body` like every other body and held to the max body size.
+#[derive(Clone)]
+pub struct HttpRoute {
+    pub path: String,
+    pub methods: Vec<HttpRequestType>,
+    pub handler: RouteHandler,
+    pub ip_filter: Option<IpFilter>,
+    pub multipart: bool,
+}
+
+impl HttpRoute {
+    /// Checks if the client may use the route, without an address there is nothing to check the
+    /// filter against so only routes without one can be used.
+    pub fn allows(&self, client_address: Option<IpAddr>) -> bool {
+        match &self.ip_filter {
+            Some(ip_filter) => {
+                client_address.is_some_and(|ip_address| ip_filter.allows(ip_address))
+            }
+            None => true,
+        }
+    }
+}
+
+impl fmt::Debug for HttpRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HttpRoute")
+            .field("path", &self.path)
+            .field("methods", &self.methods)
+            .field("ip_filter", &self.ip_filter)
+            .field("multipart", &self.multipart)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settings of a [`crate::HttpServer`] which get handed to every connection it processes.
+#[derive(Debug, Clone)]
+pub struct HttpServerSettings {
+    pub server_function: ServerFunction,
+    pub keepalive: bool,
+    pub compression: Option<CompressionConfig>,
+    pub cors: Option<CorsConfig>,
+    pub routes: Vec<HttpRoute>,
+    pub middleware: Vec<Arc<dyn HttpMiddleware>>,
+    pub limits: RequestLimits,
+    pub counters: Arc<LimitCounters>,
+    pub max_connections_per_ip: Option<usize>,
+    pub connections: Arc<ConnectionTracker>,
+    pub rate_limit: Option<RateLimiter>,
+    pub ip_filter: Option<IpFilter>,
+    pub trusted_proxies: Vec<IpNetwork>,
+    pub multipart: MultipartLimits,
+    pub error_pages: HashMap<u16, ErrorPage>,
+}
+
+impl Default for HttpServerSettings {
+    fn default() -> Self {
+        HttpServerSettings {
+            server_function: ServerFunction::Debug,
+            keepalive: false,
+            compression: None,
+            cors: None,
+            routes: Vec::new(),
+            middleware: Vec::new(),
+            limits: RequestLimits::default(),
+            counters: Arc::new(LimitCounters::default()),
+            max_connections_per_ip: None,
+            connections: Arc::new(ConnectionTracker::default()),
+            rate_limit: None,
+            ip_filter: None,
+            trusted_proxies: Vec::new(),
+            multipart: MultipartLimits::default(),
+            error_pages: HashMap::new(),
+        }
+    }
+}
+
+/// The parsed request, `request_target` is the target exactly as it was sent while
+/// `request_path` is the percent-decoded path without the query, which is parsed into `query`.
+/// `multipart/form-data` bodies sent to a route which accepts them are parsed into `parts` while
+/// they are read, leaving `body` empty.
+/// `client_address` is the address of the client the request came from (through any trusted
+/// proxies), which is only known once the request is handed to the server so it is None straight
+/// out of the parser.
 #[derive(Debug, Clone, PartialEq)]
 pub struct ParseReturnData {
-    pub http_version: f32,
+    pub http_version: HttpVersion,
     pub http_request_type: HttpRequestType,
+    pub request_target: String,
     pub request_path: String,
-    pub headers: HashMap<String, String>,
-    pub body: String,
+    pub query: HashMap<String, Vec<String>>,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub parts: Vec<MultipartPart>,
+    pub client_address: Option<IpAddr>,
+}
+
+/// An HTTP/1.1 `GET /` without any headers or body.
+impl Default for ParseReturnData {
+    fn default() -> Self {
+        ParseReturnData {
+            http_version: HttpVersion::Http11,
+            http_request_type: HttpRequestType::GET,
+            request_target: "/".to_string(),
+            request_path: "/".to_string(),
+            query: HashMap::new(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            parts: Vec::new(),
+            client_address: None,
+        }
+    }
+}
+
+impl ParseReturnData {
+    /// Which form `request_target` was written in, for an absolute-form target `request_path` is
+    /// the path from the target and the `Host` header is set to its authority.
+    pub fn target_form(&self) -> RequestTargetForm {
+        crate::http_parser::target_form(&self.request_target)
+    }
+
+    /// Gets the first value given for the query parameter.
+    pub fn query_value(&self, name: &str) -> Option<&str> {
+        self.query
+            .get(name)
+            .and_then(|values| values.first())
+            .map(|value| value.as_str())
+    }
+
+    /// Parses the cookies the client sent in its `Cookie` header.
+    pub fn cookies(&self) -> CookieJar {
+        self.headers
+            .get("Cookie")
+            .map(CookieJar::parse)
+            .unwrap_or_default()
+    }
+
+    /// Gets the first part of a `multipart/form-data` body with the name given.
+    pub fn part(&self, name: &str) -> Option<&MultipartPart> {
+        self.parts
+            .iter()
+            .find(|part| part.name.as_deref() == Some(name))
+    }
+
+    /// Parses the body as JSON, requests need a `Content-Type` of `application/json` (or a
+    /// `+json` type) and a body which is valid UTF-8.
+    pub fn json(&self) -> Result<JsonValue, StdStupidError> {
+        let is_json = self
+            .headers
+            .get("Content-Type")
+            .is_some_and(|content_type| {
+                let media_type = content_type
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_ascii_lowercase();
+                media_type == "application/json" || media_type.ends_with("+json")
+            });
+
+        if !is_json {
+            return Err(BadRequestError::new("Request body is not JSON").into());
+        }
+
+        JsonValue::parse(str::from_utf8(&self.body)?)
+    }
+
+    /// Parses the body as an `application/x-www-form-urlencoded` form, see
+    /// [`crate::http_query::parse_form()`]. Requests with any other `Content-Type` are rejected.
+    pub fn form(
+        &self,
+        form_limits: &FormLimits,
+    ) -> Result<HashMap<String, Vec<String>>, StdStupidError> {
+        let is_form = self
+            .headers
+            .get("Content-Type")
+            .is_some_and(|content_type| {
+                content_type
+                    .split(';')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            });
+
+        if !is_form {
+            return Err(BadRequestError::new("Request body is not a form").into());
+        }
+
+        parse_form(&self.body, form_limits)
+    }
 }
 
+/// A response being built, the headers are kept in a [`HeaderMap`] so they can be read, replaced
+/// or removed until the response is written out with [`HttpResponseStruct::get_response()`].
 #[derive(Debug, PartialEq)]
 pub struct HttpResponseStruct {
-    status: Vec<u8>,
-    headers: Vec<u8>,
+    version: HttpVersion,
+    status: HttpStatusCode,
+    reason: Option<String>,
+    headers: HeaderMap,
     body: Vec<u8>,
 }
 
@@ -53,57 +366,209 @@ impl Default for HttpResponseStruct {
 impl HttpResponseStruct {
     pub fn new() -> Self {
         HttpResponseStruct {
-            status: Vec::new(),
-            headers: Vec::new(),
+            version: HttpVersion::Http11,
+            status: HttpStatusCode::Ok,
+            reason: None,
+            headers: HeaderMap::new(),
             body: Vec::new(),
         }
     }
+    /// Sets the version written in the status line, which is HTTP/1.1 unless changed to match the
+    /// version of the request.
+    pub fn set_version(&mut self, version: HttpVersion) {
+        self.version = version;
+    }
+
+    /// Sets the status of the response, the status line gets the reason phrase the code was
+    /// registered with. Responses are 200 until this is called. The code has to be three digits
+    /// (`100..=999`), anything else can't be written as a status line.
     pub fn set_status(&mut self, status_code: u16) {
-        self.status = Vec::from(
-            format!(
-                "HTTP/1.1 {} {:?}\r\n",
-                status_code,
-                HttpStatusCode::from(status_code)
-            )
-            .as_bytes(),
-        )
+        debug_assert!(
+            (100..=999).contains(&status_code),
+            "Status code {status_code} is not three digits"
+        );
+        self.status = HttpStatusCode::from(status_code);
+        self.reason = None;
     }
-    pub fn add_header<T: AsRef<str>>(&mut self, header: T) {
-        self.headers
-            .extend_from_slice(format!("{}\r\n", header.as_ref()).as_bytes())
+
+    /// Sets the status with a reason phrase of our own instead of the registered one, the code has
+    /// to be three digits and the reason can't contain control characters so it can't end the
+    /// status line early.
+    pub fn set_status_with_reason<T: AsRef<str>>(
+        &mut self,
+        status_code: u16,
+        reason: T,
+    ) -> Result<(), StdStupidError> {
+        if !(100..=999).contains(&status_code) {
+            return Err(HeaderError::new(format!("Invalid status code {status_code}")).into());
+        }
+        let reason = reason.as_ref();
+        if !is_field_value(reason) {
+            return Err(HeaderError::new(format!("Invalid reason phrase {reason:?}")).into());
+        }
+
+        self.status = HttpStatusCode::from(status_code);
+        self.reason = Some(reason.trim().to_string());
+        Ok(())
+    }
+
+    pub fn status(&self) -> HttpStatusCode {
+        self.status
+    }
+
+    /// The reason phrase which will be sent in the status line.
+    pub fn reason(&self) -> &str {
+        self.reason
+            .as_deref()
+            .unwrap_or(self.status.reason_phrase())
+    }
+
+    /// Sets the header to only this value, replacing any value it already had. Fails if the name
+    /// isn't a valid token or the value contains control characters such as CR or LF.
+    pub fn set_header<N: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> Result<(), StdStupidError> {
+        let (name, value) = validate_header(name.as_ref(), value.as_ref())?;
+        self.headers.insert(name, value);
+        Ok(())
+    }
+
+    /// Adds the value to the header, keeping any value it already had, for headers which may be
+    /// sent more than once such as `Set-Cookie`. Validated the same way as
+    /// [`HttpResponseStruct::set_header()`].
+    pub fn append_header<N: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        name: N,
+        value: V,
+    ) -> Result<(), StdStupidError> {
+        let (name, value) = validate_header(name.as_ref(), value.as_ref())?;
+        self.headers.append(name, value);
+        Ok(())
+    }
+
+    /// Removes every value of the header, returning them.
+    pub fn remove_header(&mut self, name: &str) -> Vec<String> {
+        self.headers.remove(name)
+    }
+
+    /// Gets the first value of the header, ignoring the case of the name.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Gives the crate direct access to the headers for the ones it builds itself out of values
+    /// which are already known to be valid, skipping the checks done for outside callers.
+    pub(crate) fn headers_mut(&mut self) -> &mut HeaderMap {
+        &mut self.headers
     }
 
     pub fn set_body<T: AsRef<str>>(&mut self, body: T) {
         self.body.extend_from_slice(body.as_ref().as_bytes())
     }
 
+    /// Replaces the body with the raw bytes given, used for bodies which are not text such as
+    /// compressed content.
+    pub fn set_body_bytes<T: Into<Vec<u8>>>(&mut self, body: T) {
+        self.body = body.into();
+    }
+
+    pub fn get_body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Adds a `Set-Cookie` header for the cookie, every cookie gets its own header.
+    pub fn add_cookie(&mut self, cookie: &SetCookie) {
+        // The cookie checks its own parts when it is built so it is always a valid value
+        self.headers.append("Set-Cookie", cookie.to_string());
+    }
+
+    /// Sets the body to the value serialized as JSON, replacing any Content-Type which was
+    /// already set with `application/json`.
+    pub fn set_json(&mut self, value: &JsonValue) {
+        self.headers.insert("Content-Type", "application/json");
+        self.set_body_bytes(value.to_string());
+    }
+
+    /// Sets the headers every response gets, a header which was already set keeps its value so
+    /// these can be overridden before or after this is called.
     pub fn add_default_headers(&mut self) {
-        self.add_header("Server: std-stupid-http");
-        self.add_header("Content-Type: text/html");
-        self.add_header("Accept-Ranges: bytes");
-        self.add_header("Cache-Control: no-cache");
+        for (name, value) in [
+            ("Server", "std-stupid-http"),
+            ("Content-Type", "text/html"),
+            ("Accept-Ranges", "bytes"),
+            ("Cache-Control", "no-cache"),
+        ] {
+            if !self.headers.contains_key(name) {
+                self.headers.append(name, value);
+            }
+        }
     }
 
     pub fn get_response(&mut self) -> Vec<u8> {
-        let mut response_vec: Vec<u8> = Vec::new();
+        let mut response_vec: Vec<u8> = self.get_response_head();
+
+        response_vec.append(&mut self.body);
+
+        response_vec
+    }
 
-        response_vec.append(&mut self.status);
+    /// Returns the status line and headers without the body, the Content-Length is still the one
+    /// of the body so this is what gets sent in response to a HEAD request. Responses which can't
+    /// have a body (1xx and 204) get no Content-Length, every other response gets one even when it
+    /// is 0 so a client keeping the connection alive knows where the response ends.
+    pub fn get_response_head(&mut self) -> Vec<u8> {
+        let mut response_vec: Vec<u8> = Vec::new();
 
-        if !self.body.is_empty() {
-            self.add_header(format!("Content-Length: {}\r\n", self.body.len()));
+        if self.status.is_informational() || self.status == HttpStatusCode::NoContent {
+            self.headers.remove("Content-Length");
+        } else {
+            self.headers
+                .insert("Content-Length", self.body.len().to_string());
         }
 
-        response_vec.append(&mut self.headers);
+        response_vec.extend_from_slice(
+            format!(
+                "{} {} {}\r\n",
+                self.version,
+                self.status.code(),
+                self.reason()
+            )
+            .as_bytes(),
+        );
+
+        for (name, value) in self.headers.iter() {
+            response_vec.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
 
         response_vec.extend_from_slice(b"\r\n");
 
-        response_vec.append(&mut self.body);
-
         response_vec
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Hash)]
+/// Checks a header before it goes into a response, returning the value with the whitespace
+/// around it removed.
+fn validate_header<'a>(
+    name: &'a str,
+    value: &'a str,
+) -> Result<(&'a str, &'a str), StdStupidError> {
+    if !is_token(name) {
+        return Err(HeaderError::new(format!("Invalid header name {name:?}")).into());
+    }
+    if !is_field_value(value) {
+        return Err(HeaderError::new(format!("Invalid value for header {name}")).into());
+    }
+
+    Ok((name, value.trim_matches([' ', '\t'])))
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash)]
 pub enum HttpStatusCode {
     /// 100 Continue (RFC 7231)
     Continue,
@@ -365,6 +830,182 @@ impl From<u16> for HttpStatusCode {
     }
 }
 
+impl HttpStatusCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            HttpStatusCode::Continue => 100,
+            HttpStatusCode::SwitchingProtocols => 101,
+            HttpStatusCode::Processing => 102,
+            HttpStatusCode::EarlyHints => 103,
+            HttpStatusCode::Ok => 200,
+            HttpStatusCode::Created => 201,
+            HttpStatusCode::Accepted => 202,
+            HttpStatusCode::NonAuthoritativeInformation => 203,
+            HttpStatusCode::NoContent => 204,
+            HttpStatusCode::ResetContent => 205,
+            HttpStatusCode::PartialContent => 206,
+            HttpStatusCode::MultiStatus => 207,
+            HttpStatusCode::AlreadyReported => 208,
+            HttpStatusCode::IMUsed => 226,
+            HttpStatusCode::MultipleChoices => 300,
+            HttpStatusCode::MovedPermanently => 301,
+            HttpStatusCode::Found => 302,
+            HttpStatusCode::SeeOther => 303,
+            HttpStatusCode::NotModified => 304,
+            HttpStatusCode::UseProxy => 305,
+            HttpStatusCode::SwitchProxy => 306,
+            HttpStatusCode::TemporaryRedirect => 307,
+            HttpStatusCode::PermanentRedirect => 308,
+            HttpStatusCode::BadRequest => 400,
+            HttpStatusCode::Unauthorized => 401,
+            HttpStatusCode::PaymentRequired => 402,
+            HttpStatusCode::Forbidden => 403,
+            HttpStatusCode::NotFound => 404,
+            HttpStatusCode::MethodNotAllowed => 405,
+            HttpStatusCode::NotAcceptable => 406,
+            HttpStatusCode::ProxyAuthenticationRequired => 407,
+            HttpStatusCode::RequestTimeout => 408,
+            HttpStatusCode::Conflict => 409,
+            HttpStatusCode::Gone => 410,
+            HttpStatusCode::LengthRequired => 411,
+            HttpStatusCode::PreconditionFailed => 412,
+            HttpStatusCode::PayloadTooLarge => 413,
+            HttpStatusCode::UriTooLong => 414,
+            HttpStatusCode::UnsupportedMediaType => 415,
+            HttpStatusCode::RangeNotSatisfiable => 416,
+            HttpStatusCode::ExpectationFailed => 417,
+            HttpStatusCode::ImATeapot => 418,
+            HttpStatusCode::MisdirectedRequest => 421,
+            HttpStatusCode::UnprocessableEntity => 422,
+            HttpStatusCode::Locked => 423,
+            HttpStatusCode::FailedDependency => 424,
+            HttpStatusCode::UpgradeRequired => 426,
+            HttpStatusCode::PreconditionRequired => 428,
+            HttpStatusCode::TooManyRequests => 429,
+            HttpStatusCode::RequestHeaderFieldsTooLarge => 431,
+            HttpStatusCode::UnavailableForLegalReasons => 451,
+            HttpStatusCode::InternalServerError => 500,
+            HttpStatusCode::NotImplemented => 501,
+            HttpStatusCode::BadGateway => 502,
+            HttpStatusCode::ServiceUnavailable => 503,
+            HttpStatusCode::GatewayTimeout => 504,
+            HttpStatusCode::HttpVersionNotSupported => 505,
+            HttpStatusCode::VariantAlsoNegotiates => 506,
+            HttpStatusCode::InsufficientStorage => 507,
+            HttpStatusCode::LoopDetected => 508,
+            HttpStatusCode::NotExtended => 510,
+            HttpStatusCode::NetworkAuthenticationRequired => 511,
+            HttpStatusCode::Unknown(code) => *code,
+        }
+    }
+
+    /// The reason phrase the status code was registered with, codes we don't know have an empty
+    /// reason phrase which is allowed in a status line.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            HttpStatusCode::Continue => "Continue",
+            HttpStatusCode::SwitchingProtocols => "Switching Protocols",
+            HttpStatusCode::Processing => "Processing",
+            HttpStatusCode::EarlyHints => "Early Hints",
+            HttpStatusCode::Ok => "OK",
+            HttpStatusCode::Created => "Created",
+            HttpStatusCode::Accepted => "Accepted",
+            HttpStatusCode::NonAuthoritativeInformation => "Non-Authoritative Information",
+            HttpStatusCode::NoContent => "No Content",
+            HttpStatusCode::ResetContent => "Reset Content",
+            HttpStatusCode::PartialContent => "Partial Content",
+            HttpStatusCode::MultiStatus => "Multi-Status",
+            HttpStatusCode::AlreadyReported => "Already Reported",
+            HttpStatusCode::IMUsed => "IM Used",
+            HttpStatusCode::MultipleChoices => "Multiple Choices",
+            HttpStatusCode::MovedPermanently => "Moved Permanently",
+            HttpStatusCode::Found => "Found",
+            HttpStatusCode::SeeOther => "See Other",
+            HttpStatusCode::NotModified => "Not Modified",
+            HttpStatusCode::UseProxy => "Use Proxy",
+            HttpStatusCode::SwitchProxy => "Switch Proxy",
+            HttpStatusCode::TemporaryRedirect => "Temporary Redirect",
+            HttpStatusCode::PermanentRedirect => "Permanent Redirect",
+            HttpStatusCode::BadRequest => "Bad Request",
+            HttpStatusCode::Unauthorized => "Unauthorized",
+            HttpStatusCode::PaymentRequired => "Payment Required",
+            HttpStatusCode::Forbidden => "Forbidden",
+            HttpStatusCode::NotFound => "Not Found",
+            HttpStatusCode::MethodNotAllowed => "Method Not Allowed",
+            HttpStatusCode::NotAcceptable => "Not Acceptable",
+            HttpStatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            HttpStatusCode::RequestTimeout => "Request Timeout",
+            HttpStatusCode::Conflict => "Conflict",
+            HttpStatusCode::Gone => "Gone",
+            HttpStatusCode::LengthRequired => "Length Required",
+            HttpStatusCode::PreconditionFailed => "Precondition Failed",
+            HttpStatusCode::PayloadTooLarge => "Payload Too Large",
+            HttpStatusCode::UriTooLong => "URI Too Long",
+            HttpStatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatusCode::ExpectationFailed => "Expectation Failed",
+            HttpStatusCode::ImATeapot => "I'm a teapot",
+            HttpStatusCode::MisdirectedRequest => "Misdirected Request",
+            HttpStatusCode::UnprocessableEntity => "Unprocessable Entity",
+            HttpStatusCode::Locked => "Locked",
+            HttpStatusCode::FailedDependency => "Failed Dependency",
+            HttpStatusCode::UpgradeRequired => "Upgrade Required",
+            HttpStatusCode::PreconditionRequired => "Precondition Required",
+            HttpStatusCode::TooManyRequests => "Too Many Requests",
+            HttpStatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            HttpStatusCode::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            HttpStatusCode::InternalServerError => "Internal Server Error",
+            HttpStatusCode::NotImplemented => "Not Implemented",
+            HttpStatusCode::BadGateway => "Bad Gateway",
+            HttpStatusCode::ServiceUnavailable => "Service Unavailable",
+            HttpStatusCode::GatewayTimeout => "Gateway Timeout",
+            HttpStatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            HttpStatusCode::VariantAlsoNegotiates => "Variant Also Negotiates",
+            HttpStatusCode::InsufficientStorage => "Insufficient Storage",
+            HttpStatusCode::LoopDetected => "Loop Detected",
+            HttpStatusCode::NotExtended => "Not Extended",
+            HttpStatusCode::NetworkAuthenticationRequired => "Network Authentication Required",
+            HttpStatusCode::Unknown(_) => "",
+        }
+    }
+
+    /// 1xx
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    /// 2xx
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    /// 3xx
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    /// 4xx
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    /// 5xx
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+/// Formats the status the way it appears in a status line, e.g. `404 Not Found`, or only the code
+/// when there is no reason phrase.
+impl fmt::Display for HttpStatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.reason_phrase() {
+            "" => write!(f, "{}", self.code()),
+            reason => write!(f, "{} {}", self.code(), reason),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct WebSocketFrame {
     fin: bool,
@@ -573,3 +1214,117 @@ pub enum WebSocketOpCode {
     FutureControl,
     Invalid,
 }
+
+#[cfg(test)]
+mod http_struct_tests {
+    use crate::http_struct::*;
+
+    #[test]
+    fn default_headers_can_be_overridden() {
+        let mut response = HttpResponseStruct::new();
+        response.set_header("content-type", "text/plain").unwrap();
+        response.add_default_headers();
+        response.add_default_headers();
+        response.append_header("X-Tag", "a").unwrap();
+        response.append_header("X-Tag", "b").unwrap();
+
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.headers().get_all("Server").count(), 1);
+        assert_eq!(response.remove_header("x-tag"), vec!["a", "b"]);
+        assert_eq!(response.get_header("X-Tag"), None);
+    }
+
+    #[test]
+    fn rejects_header_injection() {
+        let mut response = HttpResponseStruct::new();
+
+        assert!(response
+            .set_header("X-Name", "a\r\nSet-Cookie: evil=1")
+            .is_err());
+        assert!(response.append_header("X-Name", "a\nb").is_err());
+        assert!(response.set_header("X Name", "a").is_err());
+        assert!(response.set_header("", "a").is_err());
+        assert!(response.headers().is_empty());
+    }
+
+    #[test]
+    fn serializes_headers_when_written() {
+        let mut response = HttpResponseStruct::new();
+        response.set_status(200);
+        response.set_header("Content-Length", "999").unwrap();
+        response.set_header("X-Padded", "  value\t").unwrap();
+        response.set_body("hello");
+
+        assert_eq!(
+            response.get_response(),
+            b"HTTP/1.1 200 OK\r\nX-Padded: value\r\nContent-Length: 5\r\n\r\nhello"
+        );
+
+        let mut no_content = HttpResponseStruct::new();
+        no_content.set_status(204);
+        no_content.set_header("Content-Length", "0").unwrap();
+
+        assert_eq!(
+            no_content.get_response(),
+            b"HTTP/1.1 204 No Content\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn status_lines_use_reason_phrases() {
+        let mut response = HttpResponseStruct::new();
+        response.set_status(404);
+        assert!(response
+            .get_response()
+            .starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+
+        response.set_status(599);
+        assert!(response.get_response().starts_with(b"HTTP/1.1 599 \r\n"));
+
+        response
+            .set_status_with_reason(200, "Everything Is Fine")
+            .unwrap();
+        assert_eq!(response.status(), HttpStatusCode::Ok);
+        assert!(response
+            .get_response()
+            .starts_with(b"HTTP/1.1 200 Everything Is Fine\r\n"));
+        assert!(response
+            .set_status_with_reason(200, "OK\r\nX-Injected: 1")
+            .is_err());
+        assert!(response.set_status_with_reason(42, "Answer").is_err());
+        assert!(response.set_status_with_reason(1000, "Too Far").is_err());
+        assert_eq!(response.status(), HttpStatusCode::Ok);
+    }
+
+    #[test]
+    fn status_codes_know_their_class() {
+        for code in 100..600 {
+            assert_eq!(HttpStatusCode::from(code).code(), code);
+        }
+
+        assert_eq!(HttpStatusCode::NotFound.to_string(), "404 Not Found");
+        assert_eq!(HttpStatusCode::Unknown(599).to_string(), "599");
+        assert!(HttpStatusCode::SwitchingProtocols.is_informational());
+        assert!(HttpStatusCode::NoContent.is_success());
+        assert!(HttpStatusCode::PermanentRedirect.is_redirection());
+        assert!(HttpStatusCode::ImATeapot.is_client_error());
+        assert!(HttpStatusCode::Unknown(599).is_server_error());
+        assert!(!HttpStatusCode::Unknown(600).is_server_error());
+    }
+
+    #[test]
+    fn parses_http_versions() {
+        assert_eq!(HttpVersion::parse("HTTP/1.1"), Some(HttpVersion::Http11));
+        assert_eq!(HttpVersion::parse("HTTP/2.0"), Some(HttpVersion::Http2));
+        assert_eq!(
+            HttpVersion::parse("HTTP/4.2"),
+            Some(HttpVersion::Unknown(4, 2))
+        );
+        assert_eq!(HttpVersion::parse("HTTP/1.10"), None);
+        assert_eq!(HttpVersion::parse("HTTP/1.1abc"), None);
+        assert_eq!(HttpVersion::parse("http/1.1"), None);
+        assert_eq!(HttpVersion::Http10.to_string(), "HTTP/1.0");
+        assert!(HttpVersion::Http10 < HttpVersion::Http11);
+        assert!(!HttpVersion::Http2.is_supported());
+    }
+}
@@ -4,47 +4,58 @@ use std::{
     fmt::Debug,
     fs::write,
     io::{self, prelude::*, BufReader, BufWriter, Write},
-    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    net::{IpAddr, Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    sync::Arc,
     time::{Duration, Instant},
 };
 use tracing::{debug, error, info, trace};
 
-use crate::{http_compose::compose_http_response, http_parser::*, http_struct::*};
+use crate::{
+    http_compose::compose_http_response, http_compression::*, http_cors::*, http_error_page::*,
+    http_ip_filter::*, http_limits::*, http_middleware::*, http_multipart::*, http_parser::*,
+    http_rate_limit::*, http_struct::*,
+};
 
 use errors_stupid::HttpServerError;
 use errors_stupid::StdStupidError;
-use http_compose::compose_server_error;
+use http_compose::{compose_error, compose_server_error, compose_too_many_requests, keep_alive};
 use standard_stupid::{hash_text_sha1, thread_manager::*};
 
 const DEFAULT_LISTEN_TO_PORT: u16 = 8080;
 const DEFAULT_LISTEN_TO_IP: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
 
+/// How long a websocket connection may go without a message before it is closed.
+const WEBSOCKET_TIMEOUT: Duration = Duration::from_secs(7);
+
 /// Struct that is used to define our HTTP server, given a Function, an optional IP and an optional
 /// port, and if not given will run by default on 127.0.0.1:8080. And has functions to start using
 /// the HTTP server with it's defined function
 ///
 /// ## Example Code
 ///
-/// ```rust
+/// ```no_run
+/// use errors_stupid::StdStupidError;
+/// use http_stupid::{http_struct::ServerFunction, HttpServer};
+///
 /// fn main() -> Result<(), StdStupidError> {
 ///     // Start a HTTP server listening on 127.0.0.1 on port 9182, with the ServeFile Function,
-///     and 8 threads, and keepalive enabled
-///     let IpAddressToUse: String = "127.0.0.1".to_string();
-///     let portTouse: u16 = 9182;
+///     // and 8 threads, and keepalive enabled
+///     let ip_address_to_use = "127.0.0.1";
+///     let port_to_use: u16 = 9182;
 ///
-///     let mut HttpServer = HttpServer::new(
+///     let mut http_server = HttpServer::new(
 ///         ServerFunction::ServeFile,
-///         Some(IpAddressToUse),
-///         Some(portTouse),
+///         Some(ip_address_to_use),
+///         Some(port_to_use),
 ///         8,
 ///         true
 ///     )?;
 ///
 ///     // Start the TCP listening device.
-///     HttpServer.setupListener()?;
+///     http_server.setup_listener()?;
 ///
 ///     // Start the listening loop for the HTTP server function given
-///     HttpServer.startListening()?;
+///     http_server.start_listening()?;
 ///
 ///     Ok(())
 /// }
@@ -52,15 +63,27 @@ const DEFAULT_LISTEN_TO_IP: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 1);
 #[derive(Debug)]
 pub struct HttpServer {
     listening_address: Ipv4Addr,
-    server_function: ServerFunction,
     port: u16,
     tcp_listener: Option<TcpListener>,
-    keepalive: bool,
+    settings: HttpServerSettings,
     thread_pool: ThreadPool,
 }
 
+pub mod http_auth;
 pub mod http_compose;
+pub mod http_compression;
+pub mod http_cookie;
+pub mod http_cors;
+pub mod http_error_page;
+pub mod http_headers;
+pub mod http_ip_filter;
+pub mod http_limits;
+pub mod http_middleware;
+pub mod http_multipart;
 pub mod http_parser;
+pub mod http_query;
+pub mod http_rate_limit;
+pub mod http_session;
 pub mod http_struct;
 
 impl HttpServer {
@@ -106,14 +129,130 @@ impl HttpServer {
 
         Ok(Self {
             listening_address: ip_address_to_use,
-            server_function: server_function_type,
             tcp_listener: None,
             port: port_to_use,
-            keepalive,
+            settings: HttpServerSettings {
+                server_function: server_function_type,
+                keepalive,
+                ..Default::default()
+            },
             thread_pool,
         })
     }
 
+    /// Enables compression of response bodies with the config given, or disables it again when
+    /// given None. Compression is disabled by default.
+    pub fn set_compression(&mut self, compression: Option<CompressionConfig>) {
+        self.settings.compression = compression;
+    }
+
+    /// Enables CORS handling with the config given, which answers preflight requests and adds the
+    /// `Access-Control-*` headers to responses for allowed origins, or disables it when given None.
+    pub fn set_cors(&mut self, cors: Option<CorsConfig>) {
+        self.settings.cors = cors;
+    }
+
+    /// Adds a route which answers requests to exactly the path given with the handler, as long as
+    /// the method is one of the methods given, other methods get a 405 listing the allowed ones.
+    pub fn add_route<F>(&mut self, path: &str, methods: &[HttpRequestType], handler: F)
+    where
+        F: Fn(&ParseReturnData) -> HttpResponseStruct + Send + Sync + 'static,
+    {
+        self.settings.routes.push(HttpRoute {
+            path: path.to_string(),
+            methods: methods.to_vec(),
+            handler: Arc::new(handler),
+            ip_filter: None,
+            multipart: false,
+        });
+    }
+
+    /// Sets if `multipart/form-data` bodies sent to the routes with the path given are parsed
+    /// into parts, which can be as large as the multipart limits allow with large parts spooled
+    /// to disk. Multipart bodies sent anywhere else are read like any other body.
+    pub fn set_route_multipart(&mut self, path: &str, multipart: bool) {
+        for route in self.settings.routes.iter_mut().filter(|r| r.path == path) {
+            route.multipart = multipart;
+        }
+    }
+
+    /// Sets which client addresses may use the routes with the path given, clients it doesn't
+    /// allow get a 403. Behind a proxy this is checked against the client address worked out
+    /// from `X-Forwarded-For`, see [`HttpServer::set_trusted_proxies()`].
+    pub fn set_route_ip_filter(&mut self, path: &str, ip_filter: Option<IpFilter>) {
+        for route in self.settings.routes.iter_mut().filter(|r| r.path == path) {
+            route.ip_filter = ip_filter.clone();
+        }
+    }
+
+    /// Sets which addresses may connect to the server at all, connections from other addresses
+    /// are closed as soon as they are accepted without an answer. This is checked against the
+    /// address of the peer, so behind a proxy it is the proxy which has to be allowed.
+    pub fn set_ip_filter(&mut self, ip_filter: Option<IpFilter>) {
+        self.settings.ip_filter = ip_filter;
+    }
+
+    /// Sets the proxies which are trusted to tell us the address of the client, requests coming
+    /// from them get the client address from `X-Forwarded-For` instead of the peer address. This
+    /// is the address route filters and the rate limit use. No proxies are trusted by default.
+    pub fn set_trusted_proxies(&mut self, trusted_proxies: Vec<IpNetwork>) {
+        self.settings.trusted_proxies = trusted_proxies;
+    }
+
+    /// Adds a middleware to the end of the chain, middleware runs in the order it was added before
+    /// the response is composed and in reverse order after, see [`HttpMiddleware`].
+    pub fn add_middleware<M: HttpMiddleware + 'static>(&mut self, middleware: M) {
+        self.settings.middleware.push(Arc::new(middleware));
+    }
+
+    /// Sets the largest request body in bytes the server will read, requests with a larger
+    /// `Content-Length` are answered with a 413 without reading the body, and chunked bodies get
+    /// one as soon as they grow past it. Defaults to 1 MiB.
+    pub fn set_max_body_size(&mut self, max_body_size: usize) {
+        self.settings.limits.max_body_size = max_body_size;
+    }
+
+    /// Sets the limits on the size of requests and how quickly they have to be sent, see
+    /// [`RequestLimits`] for what each of them does and the defaults.
+    pub fn set_request_limits(&mut self, limits: RequestLimits) {
+        self.settings.limits = limits;
+    }
+
+    /// Caps how many connections a single client address may have open at once, connections
+    /// over the cap are answered with a 429 and closed straight away so they never take up a
+    /// worker. Every address of an IPv6 /64 counts as the same client. None (the default) allows
+    /// any number.
+    pub fn set_max_connections_per_ip(&mut self, max_connections: Option<usize>) {
+        self.settings.max_connections_per_ip = max_connections;
+    }
+
+    /// Enables rate limiting of requests with the config given, by client address (or IPv6 /64)
+    /// unless the config has a key function, or disables it when given None. Requests over the limit get a
+    /// 429 with a `Retry-After` before their body is read.
+    pub fn set_rate_limit(&mut self, rate_limit: Option<RateLimitConfig>) {
+        self.settings.rate_limit = rate_limit.map(RateLimiter::new);
+    }
+
+    /// Returns the counters of requests refused for breaking the limits, these keep counting
+    /// while the server is listening so they can be read from another thread for monitoring.
+    pub fn limit_counters(&self) -> Arc<LimitCounters> {
+        Arc::clone(&self.settings.counters)
+    }
+
+    /// Sets the limits for `multipart/form-data` bodies sent to routes which accept them (see
+    /// [`HttpServer::set_route_multipart()`]), which are used instead of the max body size for
+    /// those requests as large parts get spooled to disk instead of kept in memory.
+    pub fn set_multipart_limits(&mut self, multipart: MultipartLimits) {
+        self.settings.multipart = multipart;
+    }
+
+    /// Sets the page sent as the body of error responses with the status given, replacing the
+    /// built in page which shows the status and reason phrase as HTML or plain text depending on
+    /// what the client accepts. Error responses which already have a body are sent as they are.
+    pub fn set_error_page(&mut self, status_code: u16, error_page: ErrorPage) {
+        self.settings.error_pages.insert(status_code, error_page);
+    }
+
     /// Starts the listener for the HTTP server, if succesful returns nothing, if not panics, most
     /// likley to happen if port is already in use and panic message will be displayed. Also sets
     /// the port to be non-blocking to allow simultanious connection proccesing.
@@ -125,7 +264,7 @@ impl HttpServer {
             Ok(o) => {
                 info!(
                     "HTTP server is now listening on {:?}:{:?} in server mode {:?}",
-                    self.listening_address, self.port, self.server_function
+                    self.listening_address, self.port, self.settings.server_function
                 );
                 o.set_nonblocking(true)?;
                 self.tcp_listener = Some(o);
@@ -139,8 +278,7 @@ impl HttpServer {
     /// After this it calls [`httpCompose::composeHttpResponse()`] with the data gotten to get the
     /// response to be used for the HTTP request and writes this back to the TcpStream.
     pub fn start_listening(&mut self) -> Result<(), StdStupidError> {
-        let server_function = self.server_function;
-        let http_keep_alive = self.keepalive;
+        let settings = Arc::new(self.settings.clone());
         for stream in self
             .tcp_listener
             .as_ref()
@@ -149,8 +287,34 @@ impl HttpServer {
         {
             match stream {
                 Ok(mut o) => {
+                    let client_address = o.peer_addr().ok().map(|address| address.ip());
+
+                    if let Some(ip_filter) = &settings.ip_filter {
+                        if !client_address.is_some_and(|ip_address| ip_filter.allows(ip_address)) {
+                            debug!("{client_address:?} is not allowed to connect, closing");
+                            settings.counters.record(403);
+                            continue;
+                        }
+                    }
+
+                    let guard = match (client_address, settings.max_connections_per_ip) {
+                        (Some(ip_address), Some(max_connections)) => {
+                            match settings.connections.acquire(ip_address, max_connections) {
+                                Some(guard) => Some(guard),
+                                None => {
+                                    refuse_connection(&settings, &mut o);
+                                    continue;
+                                }
+                            }
+                        }
+                        _ => None,
+                    };
+
+                    let settings = Arc::clone(&settings);
                     self.thread_pool.execute(move || {
-                        process_connection(server_function, http_keep_alive, &mut o)
+                        // Held until the connection is done so it counts as open till then
+                        let _guard = guard;
+                        process_connection(&settings, &mut o, client_address)
                     });
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -163,19 +327,37 @@ impl HttpServer {
     }
 }
 
+/// Answers a connection over the per address cap with a 429 from the listening thread, the
+/// socket is made non-blocking first so a client which doesn't read can't hold up accepting.
+fn refuse_connection(settings: &HttpServerSettings, stream: &mut TcpStream) {
+    debug!("Client is over the connection limit, refusing the connection");
+    settings.counters.record_refused_connection();
+
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = stream.write_all(&compose_too_many_requests(settings, 1));
+    }
+}
+
 fn process_connection(
-    server_function_type: ServerFunction,
-    http_keep_alive: bool,
+    settings: &HttpServerSettings,
     stream: &mut TcpStream,
+    client_address: Option<IpAddr>,
 ) {
+    // Reads which get nothing for this long end the connection, so a client going quiet can't
+    // keep the worker forever
+    if let Err(e) = stream.set_read_timeout(Some(settings.limits.idle_timeout)) {
+        error!("Could not set the read timeout of the connection: {e}");
+        return;
+    }
+
     let mut stream_writer = BufWriter::new(stream.try_clone().unwrap());
     let mut stream_reader = BufReader::new(stream.try_clone().unwrap());
 
     let handle = process_http_connection(
-        server_function_type,
-        http_keep_alive,
+        settings,
         &mut stream_writer,
         &mut stream_reader,
+        client_address,
     );
 
     // If the return is some it means we got an option of Some which means we need to call the
@@ -184,8 +366,9 @@ fn process_connection(
         Ok(o) => {
             if o {
                 debug!("Got a HTTP handle value of some, switching over to websocket handler");
+                let _ = stream.set_read_timeout(None);
                 let _ = process_websocket_connection(
-                    http_keep_alive,
+                    settings.keepalive,
                     &mut stream_writer,
                     &mut stream_reader,
                 );
@@ -195,38 +378,92 @@ fn process_connection(
     }
 }
 
-// Function takes in the server function, if the connection is being keep alived, and the stream
-// writer and reader. It will return a result of <bool, StdStupidError>, if the bool is true it
+// Function takes in the settings of the server, and the stream writer and reader. It will return a result of <bool, StdStupidError>, if the bool is true it
 // means it has requested to switch over to a websocket connection.
 fn process_http_connection(
-    server_function_type: ServerFunction,
-    http_keep_alive: bool,
+    settings: &HttpServerSettings,
     stream_writer: &mut BufWriter<TcpStream>,
     stream_reader: &mut BufReader<TcpStream>,
+    client_address: Option<IpAddr>,
 ) -> Result<bool, StdStupidError> {
     let mut execute_time: Instant = Instant::now();
     loop {
         let now = Instant::now();
 
-        let receive_buffer = stream_reader.fill_buf()?;
+        let request_head = match read_request_head(stream_reader, &settings.limits) {
+            Ok(request_head) => request_head,
+            Err(StdStupidError::BadRequest(e)) => {
+                debug!("Request head was refused ({}): {}", e.status, e.source);
+                settings.counters.record(e.status);
+                stream_writer.write_all(compose_error(settings, e.status).as_slice())?;
+                stream_writer.flush()?;
+                return Ok(false);
+            }
+            Err(e) => return Err(e),
+        };
 
-        let amount = receive_buffer.len();
+        let amount = request_head.len();
 
-        trace!("Recieved a message of {} bytes", amount);
+        trace!("Recieved a request head of {} bytes", amount);
 
-        if server_function_type == ServerFunction::DumpRequest {
-            write("./request.binary", receive_buffer).unwrap()
+        if settings.server_function == ServerFunction::DumpRequest {
+            write("./request.binary", &request_head).unwrap()
         }
 
         if amount == 0 {
-            stream_writer.write_all(&compose_server_error())?;
-            stream_writer.flush()?;
-            trace!("Responded to message with error");
+            debug!("Connection was closed or went quiet before a request, closing");
+            return Ok(false);
         } else {
-            match parse_http_connection(receive_buffer) {
+            let parsed = parse_http_connection(&request_head, &settings.limits).and_then(
+                |mut parse_return_data| {
+                    parse_return_data.client_address = client_address.map(|peer_address| {
+                        forwarded_client_address(
+                            peer_address,
+                            &parse_return_data.headers,
+                            &settings.trusted_proxies,
+                        )
+                    });
+
+                    if let Some(rate_limit) = &settings.rate_limit {
+                        rate_limit.check(&parse_return_data)?;
+                    }
+
+                    if check_expectation(settings, &parse_return_data)? {
+                        let mut continue_response = HttpResponseStruct::new();
+                        continue_response.set_status(100);
+                        stream_writer.write_all(&continue_response.get_response())?;
+                        stream_writer.flush()?;
+                        trace!("Sent 100 Continue, reading the body");
+                    }
+                    check_body(settings, &parse_return_data)?;
+
+                    match route_multipart_boundary(settings, &parse_return_data) {
+                        Some(boundary) => {
+                            parse_return_data.parts = read_multipart_body(
+                                stream_reader,
+                                &parse_return_data,
+                                &boundary,
+                                &settings.multipart,
+                                &settings.limits,
+                            )?
+                        }
+                        None => {
+                            parse_return_data.body = read_request_body(
+                                stream_reader,
+                                &parse_return_data,
+                                &settings.limits,
+                            )?
+                        }
+                    }
+                    Ok(parse_return_data)
+                },
+            );
+
+            match parsed {
                 Ok(w)
-                    if w.headers.get("Connection") == Some(&"Upgrade".to_string())
-                        && w.headers.get("Sec-WebSocket-Version") == Some(&"13".to_string()) =>
+                    if w.headers.contains_token("Connection", "Upgrade")
+                        && w.headers.contains_token("Upgrade", "websocket")
+                        && w.headers.get("Sec-WebSocket-Version") == Some("13") =>
                 {
                     // Need to return "Upgrade = websocket"
                     // Need to return "Connection = Upgrade"
@@ -253,39 +490,61 @@ fn process_http_connection(
                             debug!("Hashed and turned into base64 key to return: {}", base64);
 
                             response_struct.add_default_headers();
-                            response_struct.add_header(format!("Sec-WebSocket-Accept: {}", base64));
-                            response_struct.add_header("Connection: Upgrade");
-                            response_struct.add_header("Upgrade: websocket");
+                            let headers = response_struct.headers_mut();
+                            headers.insert("Sec-WebSocket-Accept", base64);
+                            headers.insert("Connection", "Upgrade");
+                            headers.insert("Upgrade", "websocket");
 
                             stream_writer.write_all(&response_struct.get_response())?;
                             stream_writer.flush()?;
-                            stream_reader.consume(amount);
                             return Ok(true);
                         }
                         None => {
-                            stream_writer.write_all(compose_server_error().as_slice())?;
+                            stream_writer.write_all(compose_server_error(settings).as_slice())?;
                             stream_writer.flush()?;
                         }
                     }
                 }
                 Ok(d) => {
-                    stream_writer.write_all(
-                        compose_http_response(server_function_type, http_keep_alive, d).as_slice(),
-                    )?;
+                    let keep_alive = keep_alive(settings, &d);
+                    stream_writer.write_all(compose_http_response(settings, d).as_slice())?;
                     stream_writer.flush()?;
                     trace!("Responded to message with sucess");
                     execute_time = Instant::now();
+
+                    if !keep_alive {
+                        debug!("Client or server did not want to keep the connection, closing");
+                        return Ok(false);
+                    }
+                }
+                Err(StdStupidError::BadRequest(e)) => {
+                    debug!("Request was malformed ({}): {}", e.status, e.source);
+                    settings.counters.record(e.status);
+                    stream_writer.write_all(compose_error(settings, e.status).as_slice())?;
+                    stream_writer.flush()?;
+                    // Whatever follows a malformed request (like a body we refused to read) can't
+                    // be trusted to be the start of the next request
+                    trace!("Responded to message with {}, closing", e.status);
+                    return Ok(false);
+                }
+                Err(StdStupidError::TooManyRequests(e)) => {
+                    debug!("{}, retry after {}s", e.source, e.retry_after);
+                    settings.counters.record(429);
+                    stream_writer
+                        .write_all(compose_too_many_requests(settings, e.retry_after).as_slice())?;
+                    stream_writer.flush()?;
+                    // The body was never read so the connection can't be used for another request
+                    return Ok(false);
                 }
                 Err(_) => {
-                    stream_writer.write_all(compose_server_error().as_slice())?;
+                    stream_writer.write_all(compose_server_error(settings).as_slice())?;
                     stream_writer.flush()?;
                     trace!("Responded to message with error");
                 }
             };
         }
-        stream_reader.consume(amount);
 
-        if now.duration_since(execute_time) > Duration::from_secs(7) || !http_keep_alive {
+        if now.duration_since(execute_time) > settings.limits.idle_timeout || !settings.keepalive {
             debug!("Connection expired or read no more data, closing");
             return Ok(false);
         }
@@ -333,7 +592,7 @@ fn process_websocket_connection(
             stream_reader.consume(result_length);
         }
 
-        if now.duration_since(execute_time) > Duration::from_secs(7) || !http_keep_alive {
+        if now.duration_since(execute_time) > WEBSOCKET_TIMEOUT || !http_keep_alive {
             debug!("Connection expired or read no more data, closing the websocket connection");
             return Ok(());
         }
@@ -359,6 +618,90 @@ mod http_stupid_tests {
         .unwrap();
     }
 
+    fn send(request: &[u8]) -> String {
+        let mut stream = TcpStream::connect("127.0.0.1:9183").unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    #[test]
+    fn limits_connections_and_requests_per_client() {
+        let mut server = HttpServer::new(
+            ServerFunction::Debug,
+            Some("127.0.0.1"),
+            Some(9183),
+            4,
+            false,
+        )
+        .unwrap();
+        server.set_max_connections_per_ip(Some(1));
+        server.set_rate_limit(Some(RateLimitConfig {
+            requests_per_second: 0.1,
+            burst: 2,
+            key: None,
+        }));
+        let counters = server.limit_counters();
+        server.setup_listener().unwrap();
+        std::thread::spawn(move || server.start_listening());
+
+        // While one connection is open the next one is refused without taking a worker
+        let held = TcpStream::connect("127.0.0.1:9183").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let refused = send(b"");
+        assert!(refused.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(refused.contains("Retry-After: 1\r\n"));
+        drop(held);
+        std::thread::sleep(Duration::from_millis(100));
+
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert!(send(request).starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(send(request).starts_with("HTTP/1.1 200 OK\r\n"));
+        let limited = send(request);
+        assert!(limited.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(limited.contains("Retry-After: 10\r\n"));
+
+        assert_eq!(
+            counters
+                .rate_limited
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+        assert_eq!(
+            counters
+                .connections_refused
+                .load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn drops_connections_the_filter_denies() {
+        let mut server = HttpServer::new(
+            ServerFunction::Debug,
+            Some("127.0.0.1"),
+            Some(9184),
+            2,
+            false,
+        )
+        .unwrap();
+        server.set_ip_filter(Some(IpFilter::default().deny("127.0.0.0/8").unwrap()));
+        let counters = server.limit_counters();
+        server.setup_listener().unwrap();
+        std::thread::spawn(move || server.start_listening());
+
+        let mut stream = TcpStream::connect("127.0.0.1:9184").unwrap();
+        let _ = stream.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.is_empty());
+        assert_eq!(
+            counters.denied.load(std::sync::atomic::Ordering::Relaxed),
+            1
+        );
+    }
+
     #[test]
     #[should_panic]
     fn double_server_on_same_port() {
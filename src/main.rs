@@ -1,4 +1,3 @@
-#![feature(addr_parse_ascii, ip, tcplistener_into_incoming)]
 #![allow(non_snake_case)]
 
 use errors_stupid::*;
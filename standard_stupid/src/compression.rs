@@ -0,0 +1,1064 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use errors_stupid::{CompressionError, StdStupidError};
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+const MAX_CHAIN_LENGTH: usize = 128;
+const TOKENS_PER_BLOCK: usize = 16384;
+const MAX_STORED_BLOCK: usize = 65535;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// The order the code length code lengths are sent in inside of a dynamic block header (RFC 1951
+// section 3.2.7)
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC32_TABLE: [u32; 256] = build_crc32_table();
+
+const fn build_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            value = if value & 1 != 0 {
+                0xEDB8_8320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            k += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+/// Calculates the CRC-32 (the ISO-HDLC one used by gzip and zip) checksum of the given data.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    crc ^ 0xFFFF_FFFF
+}
+
+/// Calculates the Adler-32 checksum of the given data, which is the checksum used as the trailer
+/// of a zlib stream.
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+
+    // 5552 is the largest amount of bytes we can sum before b could overflow a u32
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+
+    (b << 16) | a
+}
+
+/// Compresses the data given into a raw DEFLATE stream (RFC 1951), using LZ77 to find repeated
+/// sequences and then picking per block whichever of stored, fixed Huffman or dynamic Huffman
+/// encoding ends up being the smallest.
+pub fn deflate_compress(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77_tokens(data);
+    let mut writer = BitWriter::new();
+
+    if tokens.is_empty() {
+        // Just a final fixed block containing only the end of block symbol
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 2);
+        let (codes, lengths) = fixed_literal_codes();
+        writer.write_code(codes[END_OF_BLOCK], lengths[END_OF_BLOCK]);
+        return writer.finish();
+    }
+
+    let block_count = tokens.len().div_ceil(TOKENS_PER_BLOCK);
+    let mut position = 0;
+
+    for (index, block) in tokens.chunks(TOKENS_PER_BLOCK).enumerate() {
+        let block_length: usize = block.iter().map(Token::input_length).sum();
+        let raw = &data[position..position + block_length];
+        position += block_length;
+
+        write_block(&mut writer, block, raw, index + 1 == block_count);
+    }
+
+    writer.finish()
+}
+
+/// Decompresses a raw DEFLATE stream (RFC 1951) supporting stored, fixed and dynamic blocks.
+pub fn deflate_decompress(data: &[u8]) -> Result<Vec<u8>, StdStupidError> {
+    let (output, _) = inflate(data)?;
+
+    Ok(output)
+}
+
+/// Compresses the data into a gzip member (RFC 1952), which is the format used for the `gzip`
+/// content-coding in HTTP.
+pub fn gzip_compress(data: &[u8]) -> Vec<u8> {
+    // Magic, CM = deflate, no flags, no modification time, no extra flags, OS = unknown
+    let mut output: Vec<u8> = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+    output.extend_from_slice(&deflate_compress(data));
+    output.extend_from_slice(&crc32(data).to_le_bytes());
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    output
+}
+
+/// Decompresses a single gzip member (RFC 1952) and verifies its CRC-32 and size trailer.
+pub fn gzip_decompress(data: &[u8]) -> Result<Vec<u8>, StdStupidError> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b {
+        return Err(CompressionError::new("Data is not a gzip member").into());
+    }
+    if data[2] != 8 {
+        return Err(CompressionError::new("Gzip compression method is not deflate").into());
+    }
+
+    let flags = data[3];
+    let mut position = 10;
+
+    if flags & FEXTRA != 0 {
+        let extra_length = u16::from_le_bytes(
+            data.get(position..position + 2)
+                .ok_or_else(|| CompressionError::new("Gzip extra field is truncated"))?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        position += 2 + extra_length;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+                .ok_or_else(|| CompressionError::new("Gzip header string is not terminated"))?;
+            position += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        position += 2;
+    }
+
+    let compressed = data
+        .get(position..)
+        .ok_or_else(|| CompressionError::new("Gzip header is truncated"))?;
+    let (output, consumed) = inflate(compressed)?;
+
+    let trailer = compressed
+        .get(consumed..consumed + 8)
+        .ok_or_else(|| CompressionError::new("Gzip trailer is truncated"))?;
+    let expected_crc = u32::from_le_bytes(trailer[0..4].try_into().unwrap());
+    let expected_size = u32::from_le_bytes(trailer[4..8].try_into().unwrap());
+
+    if crc32(&output) != expected_crc {
+        return Err(CompressionError::new("Gzip CRC-32 does not match").into());
+    }
+    if output.len() as u32 != expected_size {
+        return Err(CompressionError::new("Gzip size does not match").into());
+    }
+
+    Ok(output)
+}
+
+/// Compresses the data into a zlib stream (RFC 1950), which is what the HTTP `deflate`
+/// content-coding actually means.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // CM = deflate with a 32K window, FLEVEL = default, and FCHECK so the header is a multiple of 31
+    let mut output: Vec<u8> = vec![0x78, 0x9c];
+
+    output.extend_from_slice(&deflate_compress(data));
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+
+    output
+}
+
+/// Decompresses a zlib stream (RFC 1950) and verifies its Adler-32 trailer.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, StdStupidError> {
+    if data.len() < 6 {
+        return Err(CompressionError::new("Data is too short to be a zlib stream").into());
+    }
+    if data[0] & 0x0f != 8 || !((data[0] as u16) << 8 | data[1] as u16).is_multiple_of(31) {
+        return Err(CompressionError::new("Zlib header is invalid").into());
+    }
+    if data[1] & 0x20 != 0 {
+        return Err(CompressionError::new("Zlib preset dictionaries are not supported").into());
+    }
+
+    let (output, consumed) = inflate(&data[2..])?;
+
+    let trailer = data
+        .get(2 + consumed..2 + consumed + 4)
+        .ok_or_else(|| CompressionError::new("Zlib trailer is truncated"))?;
+
+    if adler32(&output) != u32::from_be_bytes(trailer.try_into().unwrap()) {
+        return Err(CompressionError::new("Zlib Adler-32 does not match").into());
+    }
+
+    Ok(output)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+impl Token {
+    fn input_length(&self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => *length as usize,
+        }
+    }
+}
+
+fn hash_three_bytes(bytes: &[u8]) -> usize {
+    let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Turns the input into a list of literals and back references using hash chains to find the
+/// longest match inside of the 32K window.
+fn lz77_tokens(data: &[u8]) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut head: Vec<usize> = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous: Vec<usize> = vec![usize::MAX; WINDOW_SIZE];
+
+    let insert = |position: usize, head: &mut [usize], previous: &mut [usize]| {
+        if position + MIN_MATCH <= data.len() {
+            let hash = hash_three_bytes(&data[position..]);
+            previous[position % WINDOW_SIZE] = head[hash];
+            head[hash] = position;
+        }
+    };
+
+    let mut position = 0;
+    while position < data.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+
+        if position + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - position);
+            let mut candidate = head[hash_three_bytes(&data[position..])];
+            let mut chain = 0;
+
+            while candidate != usize::MAX
+                && position - candidate <= WINDOW_SIZE
+                && chain < MAX_CHAIN_LENGTH
+            {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[position..position + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = position - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+
+                candidate = previous[candidate % WINDOW_SIZE];
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            tokens.push(Token::Match {
+                length: best_length as u16,
+                distance: best_distance as u16,
+            });
+            for i in position..position + best_length {
+                insert(i, &mut head, &mut previous);
+            }
+            position += best_length;
+        } else {
+            tokens.push(Token::Literal(data[position]));
+            insert(position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+
+    tokens
+}
+
+fn length_code_index(length: u16) -> usize {
+    LENGTH_BASE
+        .iter()
+        .rposition(|base| *base <= length)
+        .unwrap()
+}
+
+fn distance_code_index(distance: u16) -> usize {
+    DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base <= distance)
+        .unwrap()
+}
+
+struct BitWriter {
+    output: Vec<u8>,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            output: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Writes the lowest `count` bits of value, least significant bit first.
+    fn write_bits(&mut self, value: u32, count: u8) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count as u32;
+
+        while self.bit_count >= 8 {
+            self.output.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting with their most significant bit, so they get reversed
+    /// before being written.
+    fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = (code as u32).reverse_bits() >> (32 - length as u32);
+
+        self.write_bits(reversed, length);
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit_count > 0 {
+            self.output.push(self.bit_buffer as u8);
+            self.bit_buffer = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+
+        self.output
+    }
+}
+
+fn fixed_literal_codes() -> (Vec<u16>, Vec<u8>) {
+    let mut lengths = vec![0u8; 288];
+
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+
+    (canonical_codes(&lengths), lengths)
+}
+
+fn fixed_distance_codes() -> (Vec<u16>, Vec<u8>) {
+    let lengths = vec![5u8; 30];
+
+    (canonical_codes(&lengths), lengths)
+}
+
+/// Computes Huffman code lengths for the frequencies given, if the tree ends up deeper than
+/// `max_bits` the frequencies get flattened and the tree is rebuilt until it fits.
+fn huffman_code_lengths(frequencies: &[u32], max_bits: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+
+    loop {
+        let lengths = build_huffman_lengths(&frequencies);
+
+        if lengths.iter().all(|length| *length <= max_bits) {
+            return lengths;
+        }
+
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = frequency.div_ceil(2);
+        }
+    }
+}
+
+fn build_huffman_lengths(frequencies: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; frequencies.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+    let mut parents: Vec<usize> = Vec::new();
+    let mut leaf_symbols: Vec<usize> = Vec::new();
+
+    for (symbol, frequency) in frequencies.iter().enumerate() {
+        if *frequency > 0 {
+            heap.push(Reverse((*frequency as u64, parents.len())));
+            parents.push(usize::MAX);
+            leaf_symbols.push(symbol);
+        }
+    }
+
+    if leaf_symbols.len() == 1 {
+        lengths[leaf_symbols[0]] = 1;
+        return lengths;
+    }
+
+    while heap.len() > 1 {
+        let Reverse((weight_a, node_a)) = heap.pop().unwrap();
+        let Reverse((weight_b, node_b)) = heap.pop().unwrap();
+
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[node_a] = node;
+        parents[node_b] = node;
+
+        heap.push(Reverse((weight_a + weight_b, node)));
+    }
+
+    for (leaf, symbol) in leaf_symbols.iter().enumerate() {
+        let mut depth = 0;
+        let mut node = leaf;
+        while parents[node] != usize::MAX {
+            node = parents[node];
+            depth += 1;
+        }
+        lengths[*symbol] = depth.min(u8::MAX as usize) as u8;
+    }
+
+    lengths
+}
+
+/// Assigns the canonical Huffman codes for a list of code lengths (RFC 1951 section 3.2.2).
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut length_count = [0u16; 16];
+    for length in lengths.iter().filter(|l| **l > 0) {
+        length_count[*length as usize] += 1;
+    }
+
+    let mut next_code = [0u16; 16];
+    let mut code: u16 = 0;
+    for bits in 1..16 {
+        code = (code + length_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|length| {
+            if *length == 0 {
+                0
+            } else {
+                let code = next_code[*length as usize];
+                next_code[*length as usize] += 1;
+                code
+            }
+        })
+        .collect()
+}
+
+/// Makes sure at least two symbols are in use, as a code with a single symbol is incomplete and
+/// gets rejected by some decoders.
+fn pad_frequencies(frequencies: &mut [u32]) {
+    let mut used = frequencies.iter().filter(|f| **f > 0).count();
+
+    for frequency in frequencies.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *frequency == 0 {
+            *frequency = 1;
+            used += 1;
+        }
+    }
+}
+
+/// Run length encodes the literal/length and distance code lengths into the code length
+/// alphabet, returning a list of (symbol, extra value, extra bits).
+fn code_length_symbols(lengths: &[u8]) -> Vec<(u8, u8, u8)> {
+    let mut symbols: Vec<(u8, u8, u8)> = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let length = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|l| **l == length).count();
+        i += run;
+
+        if length == 0 {
+            while run >= 11 {
+                let amount = run.min(138);
+                symbols.push((18, (amount - 11) as u8, 7));
+                run -= amount;
+            }
+            if run >= 3 {
+                symbols.push((17, (run - 3) as u8, 3));
+                run = 0;
+            }
+        } else {
+            symbols.push((length, 0, 0));
+            run -= 1;
+            while run >= 3 {
+                let amount = run.min(6);
+                symbols.push((16, (amount - 3) as u8, 2));
+                run -= amount;
+            }
+        }
+
+        for _ in 0..run {
+            symbols.push((length, 0, 0));
+        }
+    }
+
+    symbols
+}
+
+fn token_cost(token: &Token, literal_lengths: &[u8], distance_lengths: &[u8]) -> usize {
+    match token {
+        Token::Literal(byte) => literal_lengths[*byte as usize] as usize,
+        Token::Match { length, distance } => {
+            let length_index = length_code_index(*length);
+            let distance_index = distance_code_index(*distance);
+
+            literal_lengths[257 + length_index] as usize
+                + LENGTH_EXTRA_BITS[length_index] as usize
+                + distance_lengths[distance_index] as usize
+                + DISTANCE_EXTRA_BITS[distance_index] as usize
+        }
+    }
+}
+
+fn write_tokens(
+    writer: &mut BitWriter,
+    tokens: &[Token],
+    literal: (&[u16], &[u8]),
+    distance: (&[u16], &[u8]),
+) {
+    let (literal_codes, literal_lengths) = literal;
+    let (distance_codes, distance_lengths) = distance;
+
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => {
+                writer.write_code(
+                    literal_codes[*byte as usize],
+                    literal_lengths[*byte as usize],
+                );
+            }
+            Token::Match { length, distance } => {
+                let length_index = length_code_index(*length);
+                writer.write_code(
+                    literal_codes[257 + length_index],
+                    literal_lengths[257 + length_index],
+                );
+                writer.write_bits(
+                    (*length - LENGTH_BASE[length_index]) as u32,
+                    LENGTH_EXTRA_BITS[length_index],
+                );
+
+                let distance_index = distance_code_index(*distance);
+                writer.write_code(
+                    distance_codes[distance_index],
+                    distance_lengths[distance_index],
+                );
+                writer.write_bits(
+                    (*distance - DISTANCE_BASE[distance_index]) as u32,
+                    DISTANCE_EXTRA_BITS[distance_index],
+                );
+            }
+        }
+    }
+
+    writer.write_code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], is_final: bool) {
+    // Gather the frequencies for the dynamic Huffman tables
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => literal_frequencies[*byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_frequencies[257 + length_code_index(*length)] += 1;
+                distance_frequencies[distance_code_index(*distance)] += 1;
+            }
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] += 1;
+    pad_frequencies(&mut literal_frequencies);
+    pad_frequencies(&mut distance_frequencies);
+
+    let literal_lengths = huffman_code_lengths(&literal_frequencies, 15);
+    let distance_lengths = huffman_code_lengths(&distance_frequencies, 15);
+
+    let literal_count = 257.max(literal_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+    let distance_count = 1.max(distance_lengths.iter().rposition(|l| *l > 0).unwrap_or(0) + 1);
+
+    let mut all_lengths = literal_lengths[..literal_count].to_vec();
+    all_lengths.extend_from_slice(&distance_lengths[..distance_count]);
+    let length_symbols = code_length_symbols(&all_lengths);
+
+    let mut code_length_frequencies = [0u32; 19];
+    for (symbol, _, _) in &length_symbols {
+        code_length_frequencies[*symbol as usize] += 1;
+    }
+    pad_frequencies(&mut code_length_frequencies);
+    let code_length_lengths = huffman_code_lengths(&code_length_frequencies, 7);
+    let code_length_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|symbol| code_length_lengths[*symbol] > 0)
+            .unwrap_or(0)
+            + 1,
+    );
+
+    let (fixed_literal_codes, fixed_literal_lengths) = fixed_literal_codes();
+    let (fixed_distance_codes, fixed_distance_lengths) = fixed_distance_codes();
+
+    let fixed_cost: usize = tokens
+        .iter()
+        .map(|t| token_cost(t, &fixed_literal_lengths, &fixed_distance_lengths))
+        .sum::<usize>()
+        + fixed_literal_lengths[END_OF_BLOCK] as usize;
+    let dynamic_cost: usize = 14
+        + 3 * code_length_count
+        + length_symbols
+            .iter()
+            .map(|(symbol, _, extra_bits)| {
+                code_length_lengths[*symbol as usize] as usize + *extra_bits as usize
+            })
+            .sum::<usize>()
+        + tokens
+            .iter()
+            .map(|t| token_cost(t, &literal_lengths, &distance_lengths))
+            .sum::<usize>()
+        + literal_lengths[END_OF_BLOCK] as usize;
+    // Every stored block needs 4 bytes of LEN/NLEN plus the padding to a byte boundary
+    let stored_cost = raw.len() * 8 + raw.len().div_ceil(MAX_STORED_BLOCK).max(1) * 40;
+
+    if stored_cost <= fixed_cost && stored_cost <= dynamic_cost {
+        let chunk_count = raw.len().div_ceil(MAX_STORED_BLOCK).max(1);
+        for index in 0..chunk_count {
+            let chunk = &raw[(index * MAX_STORED_BLOCK).min(raw.len())
+                ..((index + 1) * MAX_STORED_BLOCK).min(raw.len())];
+
+            writer.write_bits((is_final && index + 1 == chunk_count) as u32, 1);
+            writer.write_bits(0, 2);
+            writer.align_to_byte();
+            writer
+                .output
+                .extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            writer
+                .output
+                .extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+            writer.output.extend_from_slice(chunk);
+        }
+    } else if fixed_cost <= dynamic_cost {
+        writer.write_bits(is_final as u32, 1);
+        writer.write_bits(1, 2);
+        write_tokens(
+            writer,
+            tokens,
+            (&fixed_literal_codes, &fixed_literal_lengths),
+            (&fixed_distance_codes, &fixed_distance_lengths),
+        );
+    } else {
+        writer.write_bits(is_final as u32, 1);
+        writer.write_bits(2, 2);
+        writer.write_bits((literal_count - 257) as u32, 5);
+        writer.write_bits((distance_count - 1) as u32, 5);
+        writer.write_bits((code_length_count - 4) as u32, 4);
+
+        for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+            writer.write_bits(code_length_lengths[*symbol] as u32, 3);
+        }
+
+        let code_length_codes = canonical_codes(&code_length_lengths);
+        for (symbol, extra_value, extra_bits) in &length_symbols {
+            writer.write_code(
+                code_length_codes[*symbol as usize],
+                code_length_lengths[*symbol as usize],
+            );
+            writer.write_bits(*extra_value as u32, *extra_bits);
+        }
+
+        write_tokens(
+            writer,
+            tokens,
+            (&canonical_codes(&literal_lengths), &literal_lengths),
+            (&canonical_codes(&distance_lengths), &distance_lengths),
+        );
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32, StdStupidError> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.position)
+                .ok_or_else(|| CompressionError::new("Unexpected end of deflate stream"))?;
+            self.position += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// Decoder for a canonical Huffman code, which walks the code one bit at a time using how many
+/// codes there are of each length.
+struct HuffmanDecoder {
+    length_count: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl HuffmanDecoder {
+    fn new(lengths: &[u8]) -> Result<Self, StdStupidError> {
+        let mut length_count = [0u16; 16];
+        for length in lengths {
+            length_count[*length as usize] += 1;
+        }
+        length_count[0] = 0;
+
+        // Check the code isn't over-subscribed
+        let mut left: i32 = 1;
+        for count in &length_count[1..] {
+            left <<= 1;
+            left -= *count as i32;
+            if left < 0 {
+                return Err(CompressionError::new("Huffman code is over-subscribed").into());
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for bits in 1..15 {
+            offsets[bits + 1] = offsets[bits] + length_count[bits];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Ok(HuffmanDecoder {
+            length_count,
+            symbols,
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, StdStupidError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+
+        for bits in 1..16 {
+            code |= reader.read_bits(1)? as i32;
+            let count = self.length_count[bits] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(CompressionError::new("Invalid Huffman code in deflate stream").into())
+    }
+}
+
+/// Inflates a raw DEFLATE stream returning the output and how many bytes of input were used, so
+/// the gzip and zlib wrappers can find their trailers.
+fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), StdStupidError> {
+    let mut reader = BitReader::new(data);
+    let mut output: Vec<u8> = Vec::new();
+
+    loop {
+        let is_final = reader.read_bits(1)? == 1;
+
+        match reader.read_bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let header = data
+                    .get(reader.position..reader.position + 4)
+                    .ok_or_else(|| CompressionError::new("Stored block header is truncated"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let length_complement = u16::from_le_bytes([header[2], header[3]]);
+                if length != !length_complement {
+                    return Err(
+                        CompressionError::new("Stored block length does not match NLEN").into(),
+                    );
+                }
+                reader.position += 4;
+
+                let block = data
+                    .get(reader.position..reader.position + length as usize)
+                    .ok_or_else(|| CompressionError::new("Stored block is truncated"))?;
+                output.extend_from_slice(block);
+                reader.position += length as usize;
+            }
+            1 => {
+                let (_, literal_lengths) = fixed_literal_codes();
+                let (_, distance_lengths) = fixed_distance_codes();
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &HuffmanDecoder::new(&literal_lengths)?,
+                    &HuffmanDecoder::new(&distance_lengths)?,
+                )?;
+            }
+            2 => {
+                let (literal_decoder, distance_decoder) = read_dynamic_tables(&mut reader)?;
+                inflate_block(
+                    &mut reader,
+                    &mut output,
+                    &literal_decoder,
+                    &distance_decoder,
+                )?;
+            }
+            _ => return Err(CompressionError::new("Invalid deflate block type").into()),
+        }
+
+        if is_final {
+            break;
+        }
+    }
+
+    Ok((output, reader.position))
+}
+
+fn read_dynamic_tables(
+    reader: &mut BitReader,
+) -> Result<(HuffmanDecoder, HuffmanDecoder), StdStupidError> {
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let distance_count = reader.read_bits(5)? as usize + 1;
+    let code_length_count = reader.read_bits(4)? as usize + 4;
+
+    if literal_count > 286 || distance_count > 30 {
+        return Err(CompressionError::new("Dynamic block has too many codes").into());
+    }
+
+    let mut code_length_lengths = [0u8; 19];
+    for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[*symbol] = reader.read_bits(3)? as u8;
+    }
+    let code_length_decoder = HuffmanDecoder::new(&code_length_lengths)?;
+
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_decoder.decode(reader)?;
+
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| {
+                    CompressionError::new("Repeat code with no previous code length")
+                })?;
+                (previous, 3 + reader.read_bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.read_bits(3)? as usize),
+            _ => (0, 11 + reader.read_bits(7)? as usize),
+        };
+
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err(CompressionError::new("Code lengths overflow the table").into());
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(CompressionError::new("Dynamic block has no end of block code").into());
+    }
+
+    Ok((
+        HuffmanDecoder::new(&lengths[..literal_count])?,
+        HuffmanDecoder::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literal_decoder: &HuffmanDecoder,
+    distance_decoder: &HuffmanDecoder,
+) -> Result<(), StdStupidError> {
+    loop {
+        let symbol = literal_decoder.decode(reader)? as usize;
+
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == END_OF_BLOCK {
+            return Ok(());
+        } else {
+            let length_index = symbol - 257;
+            if length_index >= LENGTH_BASE.len() {
+                return Err(CompressionError::new("Invalid length symbol").into());
+            }
+            let length = LENGTH_BASE[length_index] as usize
+                + reader.read_bits(LENGTH_EXTRA_BITS[length_index] as u32)? as usize;
+
+            let distance_index = distance_decoder.decode(reader)? as usize;
+            if distance_index >= DISTANCE_BASE.len() {
+                return Err(CompressionError::new("Invalid distance symbol").into());
+            }
+            let distance = DISTANCE_BASE[distance_index] as usize
+                + reader.read_bits(DISTANCE_EXTRA_BITS[distance_index] as u32)? as usize;
+
+            if distance > output.len() {
+                return Err(CompressionError::new("Distance goes back before the output").into());
+            }
+
+            let start = output.len() - distance;
+            for i in 0..length {
+                output.push(output[start + i]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use crate::compression::*;
+
+    const LOREM: &[u8] = include_bytes!("../../test/benchmarking/lorem.html");
+
+    // Generated with `printf 'Hello Hello Hello Hello!\n' | gzip -n`
+    const GZIP_HELLO: [u8; 30] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0xf3, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xf0, 0x40, 0x27, 0x15, 0xb9, 0x00, 0xd3, 0xe8, 0xc5, 0xae, 0x19, 0x00, 0x00, 0x00,
+    ];
+
+    fn pseudo_random_bytes(length: usize) -> Vec<u8> {
+        let mut state: u32 = 0x1234_5678;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn deflate_round_trips() {
+        let inputs: Vec<Vec<u8>> = vec![
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabcabc".to_vec(),
+            vec![0u8; 100_000],
+            pseudo_random_bytes(70_000),
+            LOREM.to_vec(),
+        ];
+
+        for input in inputs {
+            let compressed = deflate_compress(&input);
+            assert_eq!(deflate_decompress(&compressed).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn compresses_html() {
+        let compressed = gzip_compress(LOREM);
+
+        assert!(compressed.len() < LOREM.len() / 3);
+        assert_eq!(gzip_decompress(&compressed).unwrap(), LOREM);
+    }
+
+    #[test]
+    fn zlib_round_trips() {
+        let compressed = zlib_compress(LOREM);
+
+        assert_eq!(zlib_decompress(&compressed).unwrap(), LOREM);
+    }
+
+    #[test]
+    fn decompresses_gzip_from_other_encoders() {
+        assert_eq!(
+            gzip_decompress(&GZIP_HELLO).unwrap(),
+            b"Hello Hello Hello Hello!\n"
+        );
+    }
+
+    #[test]
+    fn rejects_corrupted_gzip() {
+        let mut compressed = gzip_compress(b"Some text that will get corrupted");
+        let length = compressed.len();
+        compressed[length - 6] ^= 0xff;
+
+        assert!(gzip_decompress(&compressed).is_err());
+    }
+}
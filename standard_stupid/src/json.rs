@@ -0,0 +1,557 @@
+use std::fmt::{self, Display, Write};
+
+use errors_stupid::{JsonError, StdStupidError};
+
+/// How deep arrays and objects may be nested, so a body of `[[[[...` can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value (RFC 8259). Objects keep their members in the order they were given, so
+/// serializing a parsed value gives back the same order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    /// Parses the text as a single JSON value, anything but whitespace after the value is an error.
+    pub fn parse<T: AsRef<str>>(text: T) -> Result<JsonValue, StdStupidError> {
+        let mut parser = JsonParser {
+            bytes: text.as_ref().as_bytes(),
+            position: 0,
+            depth: 0,
+        };
+
+        parser.skip_whitespace();
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+
+        if parser.position != parser.bytes.len() {
+            return Err(parser.error("Unexpected data after the value"));
+        }
+
+        Ok(value)
+    }
+
+    /// Gets the value of the member with the key given if this is an object, with duplicate keys
+    /// the last one wins like most parsers do.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(members) => members
+                .iter()
+                .rev()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<JsonValue>> {
+        match self {
+            JsonValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == JsonValue::Null
+    }
+}
+
+/// Serializes the value as compact JSON. Numbers which aren't finite can't be written in JSON so
+/// they become `null`.
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JsonValue::Null => f.write_str("null"),
+            JsonValue::Bool(boolean) => write!(f, "{boolean}"),
+            JsonValue::Number(number) if !number.is_finite() => f.write_str("null"),
+            JsonValue::Number(number) => write!(f, "{number}"),
+            JsonValue::String(string) => write_json_string(f, string),
+            JsonValue::Array(array) => {
+                f.write_char('[')?;
+                for (i, value) in array.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+            JsonValue::Object(members) => {
+                f.write_char('{')?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_json_string(f, name)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Number(value)
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self {
+        JsonValue::Number(value as f64)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(value: Vec<JsonValue>) -> Self {
+        JsonValue::Array(value)
+    }
+}
+
+/// Writes the string quoted, escaping quotes, backslashes and control characters.
+fn write_json_string<W: Write>(writer: &mut W, string: &str) -> fmt::Result {
+    writer.write_char('"')?;
+
+    for character in string.chars() {
+        match character {
+            '"' => writer.write_str("\\\"")?,
+            '\\' => writer.write_str("\\\\")?,
+            '\n' => writer.write_str("\\n")?,
+            '\r' => writer.write_str("\\r")?,
+            '\t' => writer.write_str("\\t")?,
+            '\u{08}' => writer.write_str("\\b")?,
+            '\u{0C}' => writer.write_str("\\f")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => writer.write_char(c)?,
+        }
+    }
+
+    writer.write_char('"')
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, source: &str) -> StdStupidError {
+        JsonError::new(source, self.position).into()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn expect_literal(
+        &mut self,
+        literal: &[u8],
+        value: JsonValue,
+    ) -> Result<JsonValue, StdStupidError> {
+        if self.bytes[self.position..].starts_with(literal) {
+            self.position += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("Invalid literal"))
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, StdStupidError> {
+        match self.peek() {
+            Some(b'n') => self.expect_literal(b"null", JsonValue::Null),
+            Some(b't') => self.expect_literal(b"true", JsonValue::Bool(true)),
+            Some(b'f') => self.expect_literal(b"false", JsonValue::Bool(false)),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b'-' | b'0'..=b'9') => self.parse_number(),
+            Some(b'[') => self.parse_array(),
+            Some(b'{') => self.parse_object(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), StdStupidError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        self.position += 1;
+        self.skip_whitespace();
+        Ok(())
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, StdStupidError> {
+        self.enter()?;
+        let mut array: Vec<JsonValue> = Vec::new();
+
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            self.depth -= 1;
+            return Ok(JsonValue::Array(array));
+        }
+
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                }
+                Some(b']') => {
+                    self.position += 1;
+                    self.depth -= 1;
+                    return Ok(JsonValue::Array(array));
+                }
+                _ => return Err(self.error("Expected , or ] in array")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, StdStupidError> {
+        self.enter()?;
+        let mut members: Vec<(String, JsonValue)> = Vec::new();
+
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            self.depth -= 1;
+            return Ok(JsonValue::Object(members));
+        }
+
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("Expected a string as object key"));
+            }
+            let name = self.parse_string()?;
+
+            self.skip_whitespace();
+            if self.peek() != Some(b':') {
+                return Err(self.error("Expected : after object key"));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+
+            members.push((name, self.parse_value()?));
+            self.skip_whitespace();
+
+            match self.peek() {
+                Some(b',') => {
+                    self.position += 1;
+                    self.skip_whitespace();
+                }
+                Some(b'}') => {
+                    self.position += 1;
+                    self.depth -= 1;
+                    return Ok(JsonValue::Object(members));
+                }
+                _ => return Err(self.error("Expected , or } in object")),
+            }
+        }
+    }
+
+    fn parse_hex_escape(&mut self) -> Result<u32, StdStupidError> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(|d| d.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("Invalid \\u escape"))?;
+
+        // Only ASCII hex digits are left so this can't fail
+        let value = u32::from_str_radix(std::str::from_utf8(digits).unwrap(), 16).unwrap();
+        self.position += 4;
+
+        Ok(value)
+    }
+
+    fn parse_string(&mut self) -> Result<String, StdStupidError> {
+        // Skip the opening quote
+        self.position += 1;
+        let mut string = String::new();
+
+        loop {
+            let start = self.position;
+            while !matches!(self.peek(), Some(b'"' | b'\\') | None) {
+                if self.peek().is_some_and(|c| c < 0x20) {
+                    return Err(self.error("Unescaped control character in string"));
+                }
+                self.position += 1;
+            }
+
+            // The input is a str and we only stop on ASCII, so this is always on a char boundary
+            string.push_str(std::str::from_utf8(&self.bytes[start..self.position]).unwrap());
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unexpected end of input"))?;
+                    self.position += 1;
+
+                    match escape {
+                        b'"' => string.push('"'),
+                        b'\\' => string.push('\\'),
+                        b'/' => string.push('/'),
+                        b'b' => string.push('\u{08}'),
+                        b'f' => string.push('\u{0C}'),
+                        b'n' => string.push('\n'),
+                        b'r' => string.push('\r'),
+                        b't' => string.push('\t'),
+                        b'u' => {
+                            let mut code_point = self.parse_hex_escape()?;
+
+                            if (0xD800..0xDC00).contains(&code_point) {
+                                // A high surrogate has to be followed by an escaped low surrogate
+                                if !self.bytes[self.position..].starts_with(b"\\u") {
+                                    return Err(self.error("Lone surrogate in string"));
+                                }
+                                self.position += 2;
+
+                                let low = self.parse_hex_escape()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("Lone surrogate in string"));
+                                }
+                                code_point =
+                                    0x10000 + ((code_point - 0xD800) << 10) + (low - 0xDC00);
+                            }
+
+                            string.push(
+                                char::from_u32(code_point)
+                                    .ok_or_else(|| self.error("Lone surrogate in string"))?,
+                            );
+                        }
+                        _ => return Err(self.error("Invalid escape in string")),
+                    }
+                }
+                _ => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, StdStupidError> {
+        let start = self.position;
+
+        if self.peek() == Some(b'-') {
+            self.position += 1;
+        }
+
+        match self.peek() {
+            Some(b'0') => self.position += 1,
+            Some(b'1'..=b'9') => self.skip_digits(),
+            _ => return Err(self.error("Expected a digit")),
+        }
+
+        if self.peek() == Some(b'.') {
+            self.position += 1;
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected a digit after the decimal point"));
+            }
+            self.skip_digits();
+        }
+
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            if !self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                return Err(self.error("Expected a digit in the exponent"));
+            }
+            self.skip_digits();
+        }
+
+        // The grammar was checked above, so this only has to turn it into a number
+        let text = std::str::from_utf8(&self.bytes[start..self.position]).unwrap();
+        text.parse::<f64>()
+            .map(JsonValue::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn skip_digits(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use crate::json::*;
+
+    #[test]
+    fn parses_and_serializes() {
+        let value = JsonValue::parse(
+            " {\"name\": \"std\\\"stupid\", \"tags\": [1, -2.5e3, true, null], \"nested\": {}} ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            value.get("name").and_then(|v| v.as_str()),
+            Some("std\"stupid")
+        );
+        assert_eq!(
+            value
+                .get("tags")
+                .and_then(|v| v.as_array())
+                .map(|a| a.len()),
+            Some(4)
+        );
+        assert_eq!(
+            value.to_string(),
+            "{\"name\":\"std\\\"stupid\",\"tags\":[1,-2500,true,null],\"nested\":{}}"
+        );
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        let value = JsonValue::from("a\n\u{01}\u{1F600}/");
+
+        assert_eq!(value.to_string(), "\"a\\n\\u0001\u{1F600}/\"");
+        assert_eq!(JsonValue::parse(value.to_string()).unwrap(), value);
+        assert_eq!(JsonValue::Number(f64::NAN).to_string(), "null");
+    }
+
+    #[test]
+    fn accepts_valid_documents() {
+        let accepted = [
+            ("[]", JsonValue::Array(Vec::new())),
+            ("-0", JsonValue::Number(-0.0)),
+            ("1E+2", JsonValue::Number(100.0)),
+            ("0.5e-1", JsonValue::Number(0.05)),
+            ("\"\\u00e9\\/\"", JsonValue::from("é/")),
+            ("\"\\uD834\\uDD1E\"", JsonValue::from("\u{1D11E}")),
+            ("\"\u{10FFFF}\"", JsonValue::from("\u{10FFFF}")),
+            ("\r\n\t 2 \r\n\t", JsonValue::Number(2.0)),
+            (
+                "{\"a\":1,\"a\":2}",
+                JsonValue::Object(vec![
+                    ("a".to_string(), JsonValue::Number(1.0)),
+                    ("a".to_string(), JsonValue::Number(2.0)),
+                ]),
+            ),
+        ];
+
+        for (text, expected) in accepted {
+            assert_eq!(JsonValue::parse(text).unwrap(), expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_documents() {
+        let rejected = [
+            "",
+            " ",
+            "[1,]",
+            "[,1]",
+            "{\"a\":1,}",
+            "{a:1}",
+            "{\"a\" 1}",
+            "[1 2]",
+            "01",
+            "-",
+            "+1",
+            "1.",
+            ".5",
+            "1e",
+            "0x10",
+            "NaN",
+            "Infinity",
+            "tru",
+            "nul",
+            "'single'",
+            "\"unterminated",
+            "\"tab\there\"",
+            "\"\\x41\"",
+            "\"\\u12\"",
+            "\"\\uD800\"",
+            "\"\\uDC00\"",
+            "\"\\uD800\\u0041\"",
+            "[1]]",
+            "{} {}",
+            "[1]\u{00A0}",
+            "\u{FEFF}[]",
+        ];
+
+        for text in rejected {
+            assert!(
+                matches!(JsonValue::parse(text), Err(StdStupidError::Json(_))),
+                "{text:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn reports_error_position() {
+        match JsonValue::parse("[1, 2, x]") {
+            Err(StdStupidError::Json(error)) => assert_eq!(error.position, 7),
+            other => panic!("Expected a JSON error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let deep = "[".repeat(MAX_DEPTH + 1) + &"]".repeat(MAX_DEPTH + 1);
+        assert!(JsonValue::parse(deep).is_err());
+
+        let fine = "[".repeat(MAX_DEPTH) + &"]".repeat(MAX_DEPTH);
+        assert!(JsonValue::parse(fine).is_ok());
+    }
+}
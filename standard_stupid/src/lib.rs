@@ -1,9 +1,13 @@
+pub mod compression;
+pub mod hashing;
+pub mod json;
 pub mod thread_manager;
 
 use core::str;
+use std::{fs::File, io::Read};
 
 use errors_stupid::*;
-use sha1::{Digest, Sha1, Sha1Core};
+use sha1::{Digest, Sha1};
 
 pub fn find_substring_bytes_start(
     array: &[u8],
@@ -48,9 +52,36 @@ pub fn hash_text_sha1<T: AsRef<str>>(text: T) -> Result<Vec<u8>, StdStupidError>
     Ok(ok[..].to_vec())
 }
 
+pub fn hash_text_sha256<T: AsRef<str>>(text: T) -> Result<Vec<u8>, StdStupidError> {
+    Ok(hashing::sha256(text.as_ref().as_bytes()).to_vec())
+}
+
+pub fn hash_text_md5<T: AsRef<str>>(text: T) -> Result<Vec<u8>, StdStupidError> {
+    Ok(hashing::md5(text.as_ref().as_bytes()).to_vec())
+}
+
+/// Returns the amount of random bytes asked for, read from the kernel's CSPRNG through
+/// `/dev/urandom` so they can be used for secrets, nonces and session ids.
+pub fn random_bytes(amount: usize) -> Result<Vec<u8>, StdStupidError> {
+    let mut bytes = vec![0; amount];
+
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
 #[cfg(test)]
 mod standard_stupid_tests {
-    use crate::find_substring_bytes_start;
+    use crate::{find_substring_bytes_start, random_bytes};
+
+    #[test]
+    fn random_bytes_differ() {
+        let a = random_bytes(48).unwrap();
+        let b = random_bytes(48).unwrap();
+
+        assert_eq!(a.len(), 48);
+        assert_ne!(a, b);
+    }
 
     #[test]
     #[should_panic]